@@ -0,0 +1,282 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::{futures::Notified, Notify};
+
+use crate::config::{Config, QueueConfig};
+
+/// Limits concurrent connections and instances of each server.
+/// Connections over the limits wait in a FIFO queue if configured.
+#[derive(Debug, Default)]
+pub struct Admission {
+    max_connections: Option<usize>,
+    /// Maximum instances keyed by the server name.
+    max_instances: HashMap<String, usize>,
+    queue: Option<QueueConfig>,
+    state: Mutex<State>,
+    /// Notified when a slot is released or the queue changed.
+    changed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    connections: usize,
+    instances: HashMap<String, usize>,
+    /// Waiting tickets with the server to start.
+    waiting: VecDeque<(u64, Option<String>)>,
+    next_ticket: u64,
+}
+
+impl State {
+    fn can_admit(&self, admission: &Admission, server: Option<&str>) -> bool {
+        admission
+            .max_connections
+            .is_none_or(|max| self.connections < max)
+            && server.is_none_or(|name| {
+                let running = self.instances.get(name).copied().unwrap_or(0);
+                admission
+                    .max_instances
+                    .get(name)
+                    .is_none_or(|&max| running < max)
+            })
+    }
+
+    fn admit(&mut self, admission: &Arc<Admission>, server: Option<String>) -> Admitted {
+        self.connections += 1;
+        let instance = server.map(|name| {
+            *self.instances.entry(name.clone()).or_default() += 1;
+            Permit {
+                admission: admission.clone(),
+                slot: Slot::Instance(name),
+            }
+        });
+        Admitted {
+            connection: Permit {
+                admission: admission.clone(),
+                slot: Slot::Connection,
+            },
+            instance,
+        }
+    }
+}
+
+/// Result of trying to enter.
+#[derive(Debug)]
+pub enum Entry {
+    Admitted(Admitted),
+    Queued(Ticket),
+}
+
+/// Slots held by an admitted connection.
+#[derive(Debug)]
+pub struct Admitted {
+    /// Held while the client is connected.
+    pub connection: Permit,
+    /// Held while the server is running, including while the session is detached.
+    pub instance: Option<Permit>,
+}
+
+impl Admission {
+    pub fn new(config: Option<&Config>) -> Self {
+        let config = match config {
+            Some(config) => config,
+            None => return Self::default(),
+        };
+        Self {
+            max_connections: config.max_connections,
+            max_instances: config
+                .servers
+                .iter()
+                .flatten()
+                .filter_map(|(name, server)| server.max_instances.map(|max| (name.clone(), max)))
+                .collect(),
+            queue: config.queue.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns `true` if the number of instances of server `name` is limited.
+    pub fn limits_instances(&self, name: &str) -> bool {
+        self.max_instances.contains_key(name)
+    }
+
+    /// Try to admit a connection starting `server`, or queue it if full.
+    /// `server` is the name of the server to count as an instance.
+    /// Returns `None` if the connection should be rejected.
+    pub fn enter(self: &Arc<Self>, server: Option<String>) -> Option<Entry> {
+        let mut state = self.state.lock().unwrap();
+        // Don't overtake anyone waiting for the same slots.
+        let overtaking = state
+            .waiting
+            .iter()
+            .any(|(_, waiting)| state.can_admit(self, waiting.as_deref()));
+        if !overtaking && state.can_admit(self, server.as_deref()) {
+            return Some(Entry::Admitted(state.admit(self, server)));
+        }
+
+        match &self.queue {
+            Some(queue) if state.waiting.len() < queue.size => {
+                let id = state.next_ticket;
+                state.next_ticket += 1;
+                state.waiting.push_back((id, server));
+                Some(Entry::Queued(Ticket {
+                    admission: self.clone(),
+                    id,
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn release(&self, slot: &Slot) {
+        {
+            let mut state = self.state.lock().unwrap();
+            match slot {
+                Slot::Connection => state.connections -= 1,
+                Slot::Instance(name) => {
+                    if let Some(n) = state.instances.get_mut(name) {
+                        *n -= 1;
+                    }
+                }
+            }
+        }
+        self.changed.notify_waiters();
+    }
+}
+
+/// A place in the queue. Leaves the queue when dropped.
+#[derive(Debug)]
+pub struct Ticket {
+    admission: Arc<Admission>,
+    id: u64,
+}
+
+impl Ticket {
+    /// Try to take the slots. Returns the position in the queue (1-based) if still waiting.
+    pub fn try_admit(&self) -> Result<Admitted, usize> {
+        let mut state = self.admission.state.lock().unwrap();
+        let index = state
+            .waiting
+            .iter()
+            .position(|(id, _)| *id == self.id)
+            .expect("ticket in queue");
+        // Let the ones ahead take it first.
+        let blocked = state
+            .waiting
+            .iter()
+            .take(index)
+            .any(|(_, server)| state.can_admit(&self.admission, server.as_deref()));
+        if !blocked && state.can_admit(&self.admission, state.waiting[index].1.as_deref()) {
+            let (_, server) = state.waiting.remove(index).expect("ticket in queue");
+            let admitted = state.admit(&self.admission, server);
+            drop(state);
+            self.admission.changed.notify_waiters();
+            return Ok(admitted);
+        }
+        Err(index + 1)
+    }
+
+    /// Resolves when the state may have changed. Create before calling `try_admit`.
+    pub fn changed(&self) -> Notified<'_> {
+        self.admission.changed.notified()
+    }
+
+    /// How long to wait in the queue.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.admission.queue.as_ref().map_or(0, |q| q.timeout_secs))
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let removed = {
+            let mut state = self.admission.state.lock().unwrap();
+            let before = state.waiting.len();
+            state.waiting.retain(|(id, _)| *id != self.id);
+            state.waiting.len() < before
+        };
+        if removed {
+            self.admission.changed.notify_waiters();
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Slot {
+    Connection,
+    Instance(String),
+}
+
+/// A taken slot. Released when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    admission: Arc<Admission>,
+    slot: Slot,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.admission.release(&self.slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admission(max_connections: usize, queue_size: usize) -> Arc<Admission> {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "max_connections": max_connections,
+            "queue": {"size": queue_size, "timeout_secs": 10},
+            "servers": {"a": {"command": ["a"], "max_instances": 1}},
+        }))
+        .unwrap();
+        Arc::new(Admission::new(Some(&config)))
+    }
+
+    #[test]
+    fn test_queue() {
+        let admission = admission(2, 1);
+        let first = match admission.enter(Some("a".to_owned())) {
+            Some(Entry::Admitted(admitted)) => admitted,
+            _ => panic!("expected admitted"),
+        };
+        // Over `max_instances`
+        let ticket = match admission.enter(Some("a".to_owned())) {
+            Some(Entry::Queued(ticket)) => ticket,
+            _ => panic!("expected queued"),
+        };
+        assert_eq!(ticket.try_admit().unwrap_err(), 1);
+        // Queue is full
+        assert!(admission.enter(Some("a".to_owned())).is_none());
+        // Other servers can still start
+        assert!(matches!(admission.enter(None), Some(Entry::Admitted(_))));
+
+        drop(first);
+        assert!(ticket.try_admit().is_ok());
+    }
+
+    #[test]
+    fn test_instance_outlives_connection() {
+        let admission = admission(1, 0);
+        let Admitted {
+            connection,
+            instance,
+        } = match admission.enter(Some("a".to_owned())) {
+            Some(Entry::Admitted(admitted)) => admitted,
+            _ => panic!("expected admitted"),
+        };
+        drop(connection);
+        // The connection slot is free, but the instance is still running.
+        assert!(admission.enter(Some("a".to_owned())).is_none());
+        assert!(matches!(admission.enter(None), Some(Entry::Admitted(_))));
+        drop(instance);
+        assert!(matches!(
+            admission.enter(Some("a".to_owned())),
+            Some(Entry::Admitted(_))
+        ));
+    }
+}
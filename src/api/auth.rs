@@ -0,0 +1,360 @@
+//! Authentication of requests with tokens.
+//!
+//! Tokens are sent as `Authorization: Bearer <token>`, or as query parameter `token`
+//! because browsers can't set headers on WebSocket.
+use std::{
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use warp::{Filter, Rejection};
+
+use crate::config::{AuthConfig, Secret};
+
+use super::jwt::Jwt;
+
+/// What the token allows. Unrestricted when not authenticated.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Claims {
+    /// Who the token was issued to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// Names of the servers allowed to start. Any server when not present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub servers: Option<Vec<String>>,
+    /// ID of the only workspace allowed to use. Requires isolated workspaces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    /// Expiration time in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    /// Maximum duration of the session in seconds, including while detached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_session_secs: Option<u64>,
+    /// Don't write files, both on `didSave` and through `/files`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+    /// sqls `init` drivers allowed to create a database with. Any driver when not present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sql_drivers: Option<Vec<String>>,
+}
+
+impl Claims {
+    /// Returns `true` if starting the server `name` is allowed.
+    pub fn allows_server(&self, name: &str) -> bool {
+        self.servers
+            .as_ref()
+            .is_none_or(|servers| servers.iter().any(|s| s == name))
+    }
+
+    /// Returns `true` if using the workspace `id` is allowed.
+    pub fn allows_workspace(&self, id: &str) -> bool {
+        self.workspace.as_deref().is_none_or(|w| w == id)
+    }
+
+    /// Returns `true` if creating a database with sqls `init` `driver` is allowed.
+    pub fn allows_sql_driver(&self, driver: &str) -> bool {
+        self.sql_drivers
+            .as_ref()
+            .is_none_or(|drivers| drivers.iter().any(|d| d == driver))
+    }
+
+    /// Returns `true` if the session started with `started` can be resumed or sent file
+    /// operations with these claims, i.e., they are for the same subject and workspace.
+    pub fn can_access(&self, started: &Claims) -> bool {
+        self.sub == started.sub && self.workspace == started.workspace
+    }
+
+    /// Subject for logs.
+    pub fn subject(&self) -> &str {
+        self.sub.as_deref().unwrap_or("-")
+    }
+}
+
+/// Rejection for requests without a valid token.
+#[derive(Debug)]
+pub struct Unauthorized {
+    pub reason: String,
+    /// Subject of the token if known.
+    pub subject: Option<String>,
+}
+
+impl Unauthorized {
+    pub fn with_subject(self, subject: Option<String>) -> Self {
+        Self { subject, ..self }
+    }
+}
+
+impl From<&str> for Unauthorized {
+    fn from(reason: &str) -> Self {
+        Self {
+            reason: reason.to_owned(),
+            subject: None,
+        }
+    }
+}
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Verifies tokens of one kind.
+pub trait Verifier: fmt::Debug + Send + Sync {
+    /// Returns `None` if `token` is not of the kind this verifies.
+    fn verify(&self, token: &str) -> Option<Result<Claims, Unauthorized>>;
+}
+
+/// Verifiers of the configured kinds of tokens.
+#[derive(Debug)]
+pub struct Auth {
+    verifiers: Vec<Box<dyn Verifier>>,
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut verifiers: Vec<Box<dyn Verifier>> = Vec::new();
+        if !config.tokens.is_empty() {
+            verifiers.push(Box::new(StaticTokens::new(&config.tokens)));
+        }
+        if let Some(secret) = &config.hmac_secret {
+            verifiers.push(Box::new(SignedTokens {
+                secret: secret.expose().as_bytes().to_vec(),
+            }));
+        }
+        if let Some(jwt) = &config.jwt {
+            verifiers.push(Box::new(Jwt::new(jwt)?));
+        }
+        if verifiers.is_empty() {
+            tracing::warn!("auth is configured without any tokens, all requests are rejected");
+        }
+        Ok(Self { verifiers })
+    }
+
+    /// Get the claims of `token`.
+    pub fn authenticate(&self, token: Option<&str>) -> Result<Claims, Unauthorized> {
+        let token = token.ok_or("missing token")?;
+        self.verifiers
+            .iter()
+            .find_map(|v| v.verify(token))
+            .unwrap_or_else(|| Err("invalid token".into()))
+    }
+}
+
+/// Extract the claims of the token in the request.
+/// Unrestricted if `auth` is not configured.
+pub fn with_claims(
+    auth: Option<Arc<Auth>>,
+) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then(move |header: Option<String>, query: String| {
+            let auth = auth.clone();
+            async move {
+                let auth = match auth {
+                    Some(auth) => auth,
+                    None => return Ok(Claims::default()),
+                };
+                let token = header
+                    .as_deref()
+                    .and_then(|h| h.strip_prefix("Bearer "))
+                    .map(str::to_owned)
+                    .or_else(|| {
+                        url::form_urlencoded::parse(query.as_bytes())
+                            .find(|(k, _)| k == "token")
+                            .map(|(_, v)| v.into_owned())
+                    });
+                auth.authenticate(token.as_deref()).map_err(|err| {
+                    tracing::warn!(
+                        subject = err.subject.as_deref().unwrap_or("-"),
+                        "unauthorized: {}",
+                        err.reason
+                    );
+                    warp::reject::custom(err)
+                })
+            }
+        })
+}
+
+/// Tokens from the config allowing everything.
+/// Only the digests are kept so that comparing them doesn't leak the tokens.
+#[derive(Debug)]
+struct StaticTokens {
+    digests: Vec<[u8; 32]>,
+}
+
+impl StaticTokens {
+    fn new(tokens: &[Secret]) -> Self {
+        Self {
+            digests: tokens.iter().map(|t| digest(t.expose())).collect(),
+        }
+    }
+}
+
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+impl Verifier for StaticTokens {
+    fn verify(&self, token: &str) -> Option<Result<Claims, Unauthorized>> {
+        let digest = digest(token);
+        self.digests
+            .contains(&digest)
+            .then(|| Ok(Claims::default()))
+    }
+}
+
+/// Expiring tokens with claims, `<payload>.<signature>` where `payload` is the JSON
+/// of `Claims` and `signature` is its HMAC-SHA256, both encoded in unpadded base64url.
+struct SignedTokens {
+    secret: Vec<u8>,
+}
+
+// Manually implemented to avoid showing the secret.
+impl fmt::Debug for SignedTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignedTokens").finish_non_exhaustive()
+    }
+}
+
+impl SignedTokens {
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::new_from_slice(&self.secret).expect("HMAC accepts any key size")
+    }
+
+    fn verify_signed(&self, payload: &str, signature: &str) -> Result<Claims, Unauthorized> {
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| "invalid signature")?;
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| "invalid signature")?;
+
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| "invalid payload")?;
+        let claims: Claims = serde_json::from_slice(&payload).map_err(|_| "invalid payload")?;
+        check_expiration(&claims, 0)?;
+        Ok(claims)
+    }
+
+    #[cfg(test)]
+    fn sign(&self, claims: &Claims) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+}
+
+impl Verifier for SignedTokens {
+    fn verify(&self, token: &str) -> Option<Result<Claims, Unauthorized>> {
+        let (payload, signature) = token.split_once('.')?;
+        if signature.contains('.') {
+            return None;
+        }
+
+        Some(self.verify_signed(payload, signature))
+    }
+}
+
+/// Fails if the token is expired, allowing `leeway` seconds of clock skew.
+pub(super) fn check_expiration(claims: &Claims, leeway: u64) -> Result<(), Unauthorized> {
+    let exp = claims.exp.ok_or("token without expiration")?;
+    if exp.saturating_add(leeway) <= now() {
+        return Err(Unauthorized::from("token expired").with_subject(claims.sub.clone()));
+    }
+    Ok(())
+}
+
+/// Seconds since the Unix epoch.
+pub(super) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> Auth {
+        Auth::new(&AuthConfig {
+            tokens: vec![Secret::new("static")],
+            hmac_secret: Some(Secret::new("secret")),
+            jwt: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_static_tokens() {
+        let auth = auth();
+        assert_eq!(
+            auth.authenticate(Some("static")).unwrap(),
+            Claims::default()
+        );
+        assert!(auth.authenticate(Some("other")).is_err());
+        assert!(auth.authenticate(None).is_err());
+    }
+
+    #[test]
+    fn test_can_access() {
+        let alice = Claims {
+            sub: Some("alice".to_owned()),
+            ..Claims::default()
+        };
+        assert!(alice.can_access(&alice.clone()));
+        assert!(!Claims::default().can_access(&alice));
+        let bob = Claims {
+            sub: Some("bob".to_owned()),
+            ..Claims::default()
+        };
+        assert!(!bob.can_access(&alice));
+        let other_workspace = Claims {
+            workspace: Some("other".to_owned()),
+            ..alice.clone()
+        };
+        assert!(!other_workspace.can_access(&alice));
+    }
+
+    #[test]
+    fn test_signed_tokens() {
+        let auth = auth();
+        let signer = SignedTokens {
+            secret: b"secret".to_vec(),
+        };
+        let claims = Claims {
+            sub: Some("alice".to_owned()),
+            servers: Some(vec!["rust".to_owned()]),
+            workspace: Some("abc".to_owned()),
+            exp: Some(now() + 60),
+            ..Claims::default()
+        };
+        let token = signer.sign(&claims);
+        let verified = auth.authenticate(Some(&token)).unwrap();
+        assert_eq!(verified, claims);
+        assert!(verified.allows_server("rust"));
+        assert!(!verified.allows_server("python"));
+        assert!(!verified.allows_workspace("other"));
+
+        let expired = signer.sign(&Claims {
+            exp: Some(now() - 1),
+            ..claims.clone()
+        });
+        let err = auth.authenticate(Some(&expired)).unwrap_err();
+        assert_eq!(err.reason, "token expired");
+        assert_eq!(err.subject.as_deref(), Some("alice"));
+        let forged = SignedTokens {
+            secret: b"other".to_vec(),
+        }
+        .sign(&claims);
+        assert_eq!(
+            auth.authenticate(Some(&forged)).unwrap_err().reason,
+            "invalid signature"
+        );
+    }
+}
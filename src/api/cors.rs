@@ -0,0 +1,266 @@
+//! CORS and the origins allowed to send requests.
+use std::sync::Arc;
+
+use warp::{
+    http::{header, HeaderValue, Method, StatusCode},
+    reply::Response,
+    Filter, Rejection, Reply,
+};
+
+use crate::config::CorsConfig;
+
+use super::json_error_response;
+
+/// Allowed origins, methods and headers.
+#[derive(Debug)]
+pub struct Cors {
+    /// Any origin is allowed if `None`.
+    origins: Option<Vec<OriginPattern>>,
+    methods: Vec<Method>,
+    /// Lowercase header names.
+    headers: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum OriginPattern {
+    Any,
+    Exact(String),
+    /// `<scheme>://*<suffix>` where `suffix` is `.<domain>[:<port>]`.
+    Subdomains {
+        scheme: String,
+        suffix: String,
+    },
+}
+
+impl OriginPattern {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.trim_end_matches('/').to_ascii_lowercase();
+        if pattern == "*" {
+            return Ok(Self::Any);
+        }
+        let (scheme, host) = pattern
+            .split_once("://")
+            .ok_or_else(|| format!("invalid origin {:?}", pattern))?;
+        match host.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') && !suffix.contains('*') => {
+                Ok(Self::Subdomains {
+                    scheme: scheme.to_owned(),
+                    suffix: suffix.to_owned(),
+                })
+            }
+            Some(_) => Err(format!("invalid origin {:?}", pattern)),
+            None if host.contains('*') => Err(format!("invalid origin {:?}", pattern)),
+            None => Ok(Self::Exact(pattern)),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(exact) => exact == origin,
+            Self::Subdomains { scheme, suffix } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|rest| rest.strip_prefix("://"))
+                .and_then(|host| host.strip_suffix(suffix.as_str()))
+                .is_some_and(|sub| {
+                    !sub.is_empty()
+                        && sub
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                }),
+        }
+    }
+}
+
+impl Cors {
+    /// Allow any origin if `config` is `None`.
+    pub fn new(
+        config: Option<&CorsConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let config = match config {
+            Some(config) => config.clone(),
+            None => CorsConfig {
+                allowed_origins: vec!["*".to_owned()],
+                ..CorsConfig::default()
+            },
+        };
+        let origins = config
+            .allowed_origins
+            .iter()
+            .map(|o| OriginPattern::parse(o))
+            .collect::<Result<Vec<_>, _>>()?;
+        let methods = config
+            .allowed_methods
+            .iter()
+            .map(|m| {
+                m.parse::<Method>()
+                    .map_err(|_| format!("invalid method {:?}", m))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let headers = config
+            .allowed_headers
+            .iter()
+            .map(|h| {
+                header::HeaderName::from_bytes(h.as_bytes())
+                    .map(|h| h.as_str().to_owned())
+                    .map_err(|_| format!("invalid header {:?}", h))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            origins: (!origins.contains(&OriginPattern::Any)).then_some(origins),
+            methods,
+            headers,
+        })
+    }
+
+    /// Returns `true` if requests from `origin` are allowed.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        self.origins
+            .as_ref()
+            .is_none_or(|patterns| patterns.iter().any(|p| p.matches(&origin)))
+    }
+
+    /// Response to a preflight request.
+    fn preflight(&self, origin: &str, method: &str, headers: Option<&str>) -> Response {
+        if !self.allows_origin(origin) {
+            tracing::warn!("origin {:?} is not allowed", origin);
+            return json_error_response("origin not allowed", StatusCode::FORBIDDEN);
+        }
+        if !self.methods.iter().any(|m| m.as_str() == method) {
+            return json_error_response("method not allowed", StatusCode::FORBIDDEN);
+        }
+        let headers_allowed = headers
+            .into_iter()
+            .flat_map(|h| h.split(','))
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .all(|h| self.headers.contains(&h));
+        if !headers_allowed {
+            return json_error_response("headers not allowed", StatusCode::FORBIDDEN);
+        }
+
+        let mut res = StatusCode::NO_CONTENT.into_response();
+        let methods = self
+            .methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let map = res.headers_mut();
+        allow_origin(map, origin);
+        map.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_str(&methods).expect("valid header value"),
+        );
+        map.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_str(&self.headers.join(", ")).expect("valid header value"),
+        );
+        res
+    }
+}
+
+fn allow_origin(map: &mut header::HeaderMap, origin: &str) {
+    if let Ok(value) = HeaderValue::from_str(origin) {
+        map.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        map.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+}
+
+/// Rejection for requests from origins not allowed.
+#[derive(Debug)]
+pub struct OriginNotAllowed;
+
+impl warp::reject::Reject for OriginNotAllowed {}
+
+/// Reject requests with `Origin` not allowed.
+/// Requests without `Origin` are not from browsers, and are allowed.
+pub fn check_origin(cors: Arc<Cors>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("origin")
+        .and_then(move |origin: Option<String>| {
+            let cors = cors.clone();
+            async move {
+                match origin {
+                    Some(origin) if !cors.allows_origin(&origin) => {
+                        tracing::warn!("origin {:?} is not allowed", origin);
+                        Err(warp::reject::custom(OriginNotAllowed))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Answer preflight requests, and add CORS headers to responses from `routes`.
+pub fn wrap<F, R>(
+    routes: F,
+    cors: Arc<Cors>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let preflight = {
+        let cors = cors.clone();
+        warp::options()
+            .and(warp::header::<String>("origin"))
+            .and(warp::header::<String>("access-control-request-method"))
+            .and(warp::header::optional::<String>(
+                "access-control-request-headers",
+            ))
+            .map(
+                move |origin: String, method: String, headers: Option<String>| {
+                    cors.preflight(&origin, &method, headers.as_deref())
+                },
+            )
+    };
+    let routes = warp::header::optional::<String>("origin").and(routes).map(
+        move |origin: Option<String>, reply: R| {
+            let mut res = reply.into_response();
+            if let Some(origin) = origin.filter(|o| cors.allows_origin(o)) {
+                allow_origin(res.headers_mut(), &origin);
+            }
+            res
+        },
+    );
+    preflight.or(routes).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_origin() {
+        let cors = Cors::new(Some(&CorsConfig {
+            allowed_origins: vec![
+                "https://example.com".to_owned(),
+                "https://*.example.org".to_owned(),
+                "http://*.localhost:8080".to_owned(),
+            ],
+            ..CorsConfig::default()
+        }))
+        .unwrap();
+        assert!(cors.allows_origin("https://example.com"));
+        assert!(cors.allows_origin("HTTPS://Example.com"));
+        assert!(!cors.allows_origin("http://example.com"));
+        assert!(!cors.allows_origin("https://a.example.com"));
+        assert!(cors.allows_origin("https://a.example.org"));
+        assert!(cors.allows_origin("https://a.b.example.org"));
+        assert!(!cors.allows_origin("https://example.org"));
+        assert!(!cors.allows_origin("https://evilexample.org"));
+        assert!(!cors.allows_origin("https://a.example.org.evil.com"));
+        assert!(cors.allows_origin("http://app.localhost:8080"));
+        assert!(!cors.allows_origin("http://app.localhost:8081"));
+
+        let any = Cors::new(None).unwrap();
+        assert!(any.allows_origin("https://anything.test"));
+        assert!(Cors::new(Some(&CorsConfig {
+            allowed_origins: vec!["https://a*.example.com".to_owned()],
+            ..CorsConfig::default()
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Apply `WorkspaceEdit` from the server to the files on disk.
+use std::path::{Path, PathBuf};
+
+use lsp_types::{
+    DocumentChangeOperation, DocumentChanges, FileChangeType, OneOf, ResourceOp, TextEdit,
+    WorkspaceEdit,
+};
+use tokio::fs;
+use url::Url;
+
+use super::session::offset_at;
+
+/// Apply `edit` to the files under `root`.
+/// Returns the paths changed in order, which may contain duplicates.
+pub async fn apply(
+    edit: &WorkspaceEdit,
+    root: &Path,
+) -> Result<Vec<(PathBuf, FileChangeType)>, std::io::Error> {
+    let mut changed = Vec::new();
+    // `documentChanges` is preferred over `changes` if both are present.
+    if let Some(document_changes) = &edit.document_changes {
+        match document_changes {
+            DocumentChanges::Edits(edits) => {
+                for edit in edits {
+                    let path = get_path(root, &edit.text_document.uri)?;
+                    edit_file(&path, &text_edits(&edit.edits)).await?;
+                    changed.push((path, FileChangeType::CHANGED));
+                }
+            }
+
+            DocumentChanges::Operations(ops) => {
+                for op in ops {
+                    match op {
+                        DocumentChangeOperation::Edit(edit) => {
+                            let path = get_path(root, &edit.text_document.uri)?;
+                            edit_file(&path, &text_edits(&edit.edits)).await?;
+                            changed.push((path, FileChangeType::CHANGED));
+                        }
+
+                        DocumentChangeOperation::Op(op) => {
+                            changed.append(&mut resource_op(op, root).await?);
+                        }
+                    }
+                }
+            }
+        }
+    } else if let Some(changes) = &edit.changes {
+        for (uri, edits) in changes {
+            let path = get_path(root, uri)?;
+            edit_file(&path, &edits.iter().collect::<Vec<_>>()).await?;
+            changed.push((path, FileChangeType::CHANGED));
+        }
+    }
+    Ok(changed)
+}
+
+fn text_edits(edits: &[OneOf<TextEdit, lsp_types::AnnotatedTextEdit>]) -> Vec<&TextEdit> {
+    edits
+        .iter()
+        .map(|edit| match edit {
+            OneOf::Left(edit) => edit,
+            OneOf::Right(annotated) => &annotated.text_edit,
+        })
+        .collect()
+}
+
+/// Path of `uri`. Fails unless it's a file under `root`.
+fn get_path(root: &Path, uri: &Url) -> Result<PathBuf, std::io::Error> {
+    match uri.to_file_path() {
+        Ok(path) if path.starts_with(root) => Ok(path),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{} is not under the project root", uri),
+        )),
+    }
+}
+
+async fn edit_file(path: &Path, edits: &[&TextEdit]) -> Result<(), std::io::Error> {
+    tracing::debug!("editing file {:?}", path);
+    let mut text = fs::read_to_string(path).await?;
+    apply_text_edits(&mut text, edits);
+    fs::write(path, text).await
+}
+
+/// Apply `edits` to `text`. The ranges of `edits` refer to the original `text`,
+/// and the ones inserting at the same position are inserted in order.
+fn apply_text_edits(text: &mut String, edits: &[&TextEdit]) {
+    let mut edits = edits
+        .iter()
+        .map(|edit| {
+            let start = offset_at(text, edit.range.start);
+            let end = offset_at(text, edit.range.end).max(start);
+            (start, end, &edit.new_text)
+        })
+        .collect::<Vec<_>>();
+    // Stable, so the later one of the edits at the same position is applied first.
+    edits.sort_by_key(|(start, _, _)| *start);
+    for (start, end, new_text) in edits.into_iter().rev() {
+        text.replace_range(start..end, new_text);
+    }
+}
+
+async fn resource_op(
+    op: &ResourceOp,
+    root: &Path,
+) -> Result<Vec<(PathBuf, FileChangeType)>, std::io::Error> {
+    match op {
+        ResourceOp::Create(create) => {
+            let path = get_path(root, &create.uri)?;
+            let options = create.options.as_ref();
+            if path.exists() && !options.and_then(|o| o.overwrite).unwrap_or(false) {
+                return if options.and_then(|o| o.ignore_if_exists).unwrap_or(false) {
+                    Ok(Vec::new())
+                } else {
+                    Err(already_exists(&create.uri))
+                };
+            }
+            tracing::debug!("creating file {:?}", path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(&path, "").await?;
+            Ok(vec![(path, FileChangeType::CREATED)])
+        }
+
+        ResourceOp::Rename(rename) => {
+            let from = get_path(root, &rename.old_uri)?;
+            let to = get_path(root, &rename.new_uri)?;
+            let options = rename.options.as_ref();
+            if to.exists() && !options.and_then(|o| o.overwrite).unwrap_or(false) {
+                return if options.and_then(|o| o.ignore_if_exists).unwrap_or(false) {
+                    Ok(Vec::new())
+                } else {
+                    Err(already_exists(&rename.new_uri))
+                };
+            }
+            tracing::debug!("renaming file {:?} to {:?}", from, to);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::rename(&from, &to).await?;
+            Ok(vec![
+                (from, FileChangeType::DELETED),
+                (to, FileChangeType::CREATED),
+            ])
+        }
+
+        ResourceOp::Delete(delete) => {
+            let path = get_path(root, &delete.uri)?;
+            let options = delete.options.as_ref();
+            if !path.exists()
+                && options
+                    .and_then(|o| o.ignore_if_not_exists)
+                    .unwrap_or(false)
+            {
+                return Ok(Vec::new());
+            }
+            tracing::debug!("deleting {:?}", path);
+            if path.is_dir() && options.and_then(|o| o.recursive).unwrap_or(false) {
+                fs::remove_dir_all(&path).await?;
+            } else if path.is_dir() {
+                fs::remove_dir(&path).await?;
+            } else {
+                fs::remove_file(&path).await?;
+            }
+            Ok(vec![(path, FileChangeType::DELETED)])
+        }
+    }
+}
+
+fn already_exists(uri: &Url) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!("{} already exists", uri),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use lsp_types::{Position, Range};
+
+    use super::*;
+
+    fn edit(start: (u32, u32), end: (u32, u32), new_text: &str) -> TextEdit {
+        TextEdit::new(
+            Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            new_text.to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_apply_text_edits() {
+        let mut text = "package foo;\n\nimport foo.Bar;\n".to_owned();
+        apply_text_edits(
+            &mut text,
+            &[
+                &edit((2, 7), (2, 10), "baz"),
+                &edit((0, 8), (0, 11), "baz"),
+                &edit((1, 0), (1, 0), "// a\n"),
+                &edit((1, 0), (1, 0), "// b\n"),
+            ],
+        );
+        assert_eq!(text, "package baz;\n// a\n// b\n\nimport baz.Bar;\n");
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let root = std::env::temp_dir().join(format!("lsp-ws-proxy-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/Main.java"), "import foo.Bar;\n").unwrap();
+
+        let main = Url::from_file_path(root.join("src/Main.java")).unwrap();
+        let edit = WorkspaceEdit::new(HashMap::from([(
+            main.clone(),
+            vec![edit((0, 7), (0, 10), "baz")],
+        )]));
+        assert_eq!(
+            apply(&edit, &root).await.unwrap(),
+            vec![(root.join("src/Main.java"), FileChangeType::CHANGED)]
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("src/Main.java")).unwrap(),
+            "import baz.Bar;\n"
+        );
+
+        let outside = Url::from_file_path(std::env::temp_dir().join("Main.java")).unwrap();
+        let edit = WorkspaceEdit::new(HashMap::from([(outside, vec![])]));
+        assert!(apply(&edit, &root).await.is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,15 +1,33 @@
 use std::{
     convert::Infallible,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
-use lsp_types::{FileChangeType, FileEvent};
+use globset::GlobBuilder;
+use lsp_types::{
+    FileChangeType, FileEvent, FileOperationPatternKind, FileOperationRegistrationOptions,
+};
 use thiserror::Error;
 use tokio::fs;
 use url::Url;
 use warp::{http::StatusCode, Filter, Rejection, Reply};
 
-use super::{json_body, json_response, with_context};
+use crate::lsp::{self, types::Id};
+
+use super::{
+    auth::{self, Auth, Claims},
+    cors::{self, Cors},
+    edit, json_body, json_error_response, json_response,
+    rate_limit::{self, IpLimiter},
+    session::{SessionHandle, Sessions},
+    with_context,
+    workspace::Workspaces,
+};
+
+/// How long to wait for the server to respond to `workspace/willRenameFiles`.
+const WILL_RENAME_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug, Error)]
 enum Error {
@@ -40,6 +58,9 @@ enum Error {
         to: String,
         source: std::io::Error,
     },
+
+    #[error("failed to apply edits from the server: {0}")]
+    ApplyEdit(std::io::Error),
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -76,9 +97,35 @@ enum Operation {
     Rename { from: String, to: String },
 }
 
+/// A file or directory changed by an operation.
+#[derive(Debug)]
+struct Change {
+    /// Path relative to `cwd`.
+    path: String,
+    is_dir: bool,
+    typ: FileChangeType,
+}
+
+impl Change {
+    fn new(path: &str, is_dir: bool, typ: FileChangeType) -> Self {
+        Self {
+            path: path.to_owned(),
+            is_dir,
+            typ,
+        }
+    }
+
+    fn event<P>(&self, cwd: P, remap: bool) -> FileEvent
+    where
+        P: AsRef<Path>,
+    {
+        FileEvent::new(path_uri(cwd, &self.path, self.is_dir, remap), self.typ)
+    }
+}
+
 impl Operation {
     /// Perform operation relative to `cwd`.
-    async fn perform<P>(&self, cwd: P, remap: bool) -> Result<Vec<FileEvent>, Error>
+    async fn perform<P>(&self, cwd: P) -> Result<Vec<Change>, Error>
     where
         P: AsRef<Path>,
     {
@@ -95,12 +142,13 @@ impl Operation {
                         source,
                     })?;
 
-                Ok(vec![FileEvent::new(
-                    path_uri(&cwd, path, false, remap),
+                Ok(vec![Change::new(
+                    path,
+                    false,
                     if create {
-                        FileChangeType::Created
+                        FileChangeType::CREATED
                     } else {
-                        FileChangeType::Changed
+                        FileChangeType::CHANGED
                     },
                 )])
             }
@@ -116,10 +164,7 @@ impl Operation {
                     })?;
                 remove_empty_parents(&cwd, path).await;
 
-                Ok(vec![FileEvent::new(
-                    path_uri(&cwd, path, false, remap),
-                    FileChangeType::Deleted,
-                )])
+                Ok(vec![Change::new(path, false, FileChangeType::DELETED)])
             }
 
             Operation::Rename { from, to } => {
@@ -140,13 +185,14 @@ impl Operation {
 
                 let is_dir = dst.is_dir();
                 Ok(vec![
-                    FileEvent::new(path_uri(&cwd, from, is_dir, remap), FileChangeType::Deleted),
-                    FileEvent::new(
-                        path_uri(&cwd, to, is_dir, remap),
+                    Change::new(from, is_dir, FileChangeType::DELETED),
+                    Change::new(
+                        to,
+                        is_dir,
                         if create {
-                            FileChangeType::Created
+                            FileChangeType::CREATED
                         } else {
-                            FileChangeType::Changed
+                            FileChangeType::CHANGED
                         },
                     ),
                 ])
@@ -159,11 +205,14 @@ fn get_path<P>(cwd: P, path: &str) -> Result<PathBuf, Error>
 where
     P: AsRef<Path>,
 {
-    let apath = cwd.as_ref().join(path);
-    if !apath.starts_with(&cwd) {
+    // `starts_with` doesn't resolve `..`, so only allow plain relative paths.
+    let is_relative = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !is_relative {
         return Err(Error::NotProjectPath(path.to_owned()));
     }
-    Ok(apath)
+    Ok(cwd.as_ref().join(path))
 }
 
 async fn create_parent_dirs<P, Q>(cwd: P, path: Q) -> Result<(), Error>
@@ -176,7 +225,7 @@ where
         fs::create_dir_all(cwd.as_ref().join(parent))
             .await
             .map_err(|source| Error::CreateDirs {
-                path: parent.to_str().expect("utf-8").to_owned(),
+                path: parent.to_string_lossy().into_owned(),
                 source,
             })?;
     }
@@ -250,10 +299,58 @@ fn test_path_uri() {
     );
 }
 
+#[tokio::test]
+async fn test_operations_outside_project() {
+    let root = std::env::temp_dir().join(format!("lsp-ws-proxy-files-{}", std::process::id()));
+    let (cwd, other) = (root.join("session"), root.join("other"));
+    std::fs::create_dir_all(&cwd).unwrap();
+    std::fs::create_dir_all(&other).unwrap();
+    std::fs::write(cwd.join("inside.txt"), "inside").unwrap();
+    std::fs::write(other.join("secret.txt"), "secret").unwrap();
+
+    let outside = |path: &str| matches!(get_path(&cwd, path), Err(Error::NotProjectPath(_)));
+    assert!(outside("../other/secret.txt"));
+    assert!(outside("src/../../other/secret.txt"));
+    assert!(outside("/etc/passwd"));
+    assert!(!outside("./src/inside.txt"));
+
+    let operations = [
+        Operation::Write {
+            path: "../other/new.txt".to_owned(),
+            contents: "new".to_owned(),
+        },
+        Operation::Remove {
+            path: "../other/secret.txt".to_owned(),
+        },
+        Operation::Rename {
+            from: "../other/secret.txt".to_owned(),
+            to: "stolen.txt".to_owned(),
+        },
+        Operation::Rename {
+            from: "inside.txt".to_owned(),
+            to: "../other/moved.txt".to_owned(),
+        },
+    ];
+    for op in &operations {
+        assert!(matches!(
+            op.perform(&cwd).await,
+            Err(Error::NotProjectPath(_))
+        ));
+    }
+    assert!(!other.join("new.txt").exists());
+    assert!(!other.join("moved.txt").exists());
+    assert!(!cwd.join("stolen.txt").exists());
+    assert!(other.join("secret.txt").exists());
+    assert!(cwd.join("inside.txt").exists());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
 #[derive(Debug, serde::Serialize)]
 struct Response {
     /// `FileEvent`s for `workspace/didChangeWatchedFiles` notification.
     changes: Vec<FileEvent>,
+    /// `true` if the proxy notified the server of the session, and the client shouldn't.
+    notified: bool,
     /// Any errors that occured trying to perform operations.
     #[serde(skip_serializing_if = "Option::is_none")]
     errors: Option<Vec<OperationError>>,
@@ -269,6 +366,23 @@ struct OperationError {
 pub struct Context {
     pub cwd: PathBuf,
     pub remap: bool,
+    /// Operations are performed in the workspace of the session if set.
+    pub workspaces: Option<Arc<Workspaces>>,
+    /// Require a token if set.
+    pub auth: Option<Arc<Auth>>,
+    /// Origins allowed to send requests from browsers.
+    pub cors: Arc<Cors>,
+    /// Limits the rate of requests from each IP address if set.
+    pub rate_limit: Option<Arc<IpLimiter>>,
+    /// Connected sessions to notify of the changes.
+    pub sessions: Arc<Sessions>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Query {
+    /// ID of the session to perform operations in its workspace, and to notify of the changes.
+    /// Required when workspaces are isolated.
+    session: Option<String>,
 }
 
 /// Handler for `POST /files`
@@ -276,20 +390,98 @@ pub fn handler(ctx: Context) -> impl Filter<Extract = impl Reply, Error = Reject
     warp::post()
         .and(warp::path("files"))
         .and(warp::path::end())
+        .and(cors::check_origin(ctx.cors.clone()))
+        .and(rate_limit::by_ip(ctx.rate_limit.clone()))
+        .and(auth::with_claims(ctx.auth.clone()))
         .and(with_context(ctx))
+        .and(warp::query::<Query>())
         .and(json_body::<Payload>())
         .and_then(handle_operations)
 }
 
-#[tracing::instrument(level = "debug", skip(ctx, payload))]
-async fn handle_operations(ctx: Context, payload: Payload) -> Result<impl Reply, Infallible> {
+#[tracing::instrument(level = "debug", skip(claims, ctx, payload))]
+async fn handle_operations(
+    claims: Claims,
+    ctx: Context,
+    query: Query,
+    payload: Payload,
+) -> Result<impl Reply, Infallible> {
+    if claims.read_only {
+        tracing::warn!(subject = claims.subject(), "the token is read-only");
+        return Ok(json_error_response(
+            "the token is read-only",
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    let cwd = match (&ctx.workspaces, &query.session) {
+        (Some(workspaces), Some(id)) => match workspaces.get(id) {
+            Some((name, path)) if claims.allows_workspace(&name) => path,
+            Some(_) => {
+                tracing::warn!(
+                    subject = claims.subject(),
+                    "the token does not allow the workspace"
+                );
+                return Ok(json_error_response(
+                    "the token does not allow the workspace",
+                    StatusCode::FORBIDDEN,
+                ));
+            }
+            None => {
+                return Ok(json_error_response(
+                    "no workspace for the session",
+                    StatusCode::NOT_FOUND,
+                ))
+            }
+        },
+        (Some(_), None) => {
+            return Ok(json_error_response(
+                "query parameter `session` is required",
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+        (None, _) if claims.workspace.is_some() => {
+            tracing::warn!(
+                subject = claims.subject(),
+                "the token is limited to a workspace, but workspaces are shared"
+            );
+            return Ok(json_error_response(
+                "the token is limited to a workspace, but workspaces are shared",
+                StatusCode::FORBIDDEN,
+            ));
+        }
+        (None, _) => ctx.cwd,
+    };
+
+    // Notify the server of the session if connected and started by the same subject.
+    let sessions = &ctx.sessions;
+    let server = query
+        .session
+        .as_deref()
+        .and_then(|id| sessions.handle(id, &claims))
+        .map(Server::new);
+
     let mut errors = Vec::new();
     let mut changes = Vec::new();
     // Do them one by one in order
     for op in payload.operations {
-        match op.perform(&ctx.cwd, ctx.remap).await {
-            Ok(mut events) => {
-                changes.append(&mut events);
+        let result = match (&server, &op) {
+            (Some(server), Operation::Rename { from, to }) => {
+                match server.will_rename(&cwd, from, to).await {
+                    Ok(mut edited) => {
+                        changes.append(&mut edited);
+                        op.perform(&cwd).await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            _ => op.perform(&cwd).await,
+        };
+        match result {
+            Ok(mut performed) => {
+                if let Some(server) = &server {
+                    server.did_perform(&cwd, &op, &performed).await;
+                }
+                changes.append(&mut performed);
             }
             Err(err) => {
                 errors.push(OperationError {
@@ -300,10 +492,203 @@ async fn handle_operations(ctx: Context, payload: Payload) -> Result<impl Reply,
         }
     }
 
+    let notified = match &server {
+        Some(server) => server.did_change_watched_files(&cwd, &changes).await,
+        None => false,
+    };
+    let remap = ctx.remap;
+    let changes = changes.iter().map(|c| c.event(&cwd, remap)).collect();
     let (errors, status) = if errors.is_empty() {
         (None, StatusCode::OK)
     } else {
         (Some(errors), StatusCode::UNPROCESSABLE_ENTITY)
     };
-    Ok(json_response(&Response { changes, errors }, status))
+    Ok(json_response(
+        &Response {
+            changes,
+            notified,
+            errors,
+        },
+        status,
+    ))
+}
+
+/// Server of the session to send the file operations to.
+struct Server {
+    handle: SessionHandle,
+    /// `workspace.fileOperations` of the server.
+    file_operations: lsp_types::WorkspaceFileOperationsServerCapabilities,
+}
+
+impl Server {
+    fn new(handle: SessionHandle) -> Self {
+        let file_operations = handle.file_operations().unwrap_or_default();
+        Self {
+            handle,
+            file_operations,
+        }
+    }
+
+    /// Send `workspace/willRenameFiles` if the server is interested,
+    /// and apply the `WorkspaceEdit` from the server before renaming.
+    async fn will_rename(&self, cwd: &Path, from: &str, to: &str) -> Result<Vec<Change>, Error> {
+        let is_dir = get_path(cwd, from)?.is_dir();
+        let (from_uri, to_uri) = (
+            path_uri(cwd, from, is_dir, false),
+            path_uri(cwd, to, is_dir, false),
+        );
+        if !matches(&self.file_operations.will_rename, &from_uri, is_dir) {
+            return Ok(Vec::new());
+        }
+
+        let request = lsp::Request::WillRenameFiles {
+            id: Id::String(format!("lsp-ws-proxy/files/{}", uuid::Uuid::new_v4())),
+            params: lsp_types::RenameFilesParams {
+                files: vec![lsp_types::FileRename {
+                    old_uri: from_uri.into(),
+                    new_uri: to_uri.into(),
+                }],
+            },
+        };
+        let edit =
+            match tokio::time::timeout(WILL_RENAME_TIMEOUT, self.handle.request(request)).await {
+                Ok(Some(lsp::Response::Success { result, .. })) => serde_json::to_value(result)
+                    .and_then(serde_json::from_value::<Option<lsp_types::WorkspaceEdit>>)
+                    .unwrap_or_else(|err| {
+                        tracing::warn!("invalid response to willRenameFiles: {}", err);
+                        None
+                    }),
+                Ok(Some(lsp::Response::Failure { error, .. })) => {
+                    tracing::warn!("willRenameFiles failed: {}", error.message);
+                    None
+                }
+                Ok(None) => None,
+                Err(_) => {
+                    tracing::warn!("timed out waiting for willRenameFiles response");
+                    None
+                }
+            };
+        let edit = match edit {
+            Some(edit) => edit,
+            None => return Ok(Vec::new()),
+        };
+
+        let edited = edit::apply(&edit, cwd).await.map_err(Error::ApplyEdit)?;
+        Ok(edited
+            .into_iter()
+            .filter_map(|(path, typ)| {
+                let path = path.strip_prefix(cwd).ok()?.to_str()?.to_owned();
+                Some(Change::new(&path, false, typ))
+            })
+            .collect())
+    }
+
+    /// Send `workspace/did{Create,Rename,Delete}Files` for `op` if the server is interested.
+    async fn did_perform(&self, cwd: &Path, op: &Operation, performed: &[Change]) {
+        let ops = &self.file_operations;
+        let notification = match (op, performed) {
+            (Operation::Write { .. }, [created]) if created.typ == FileChangeType::CREATED => {
+                let uri = path_uri(cwd, &created.path, false, false);
+                matches(&ops.did_create, &uri, false).then(|| lsp::Notification::DidCreateFiles {
+                    params: lsp_types::CreateFilesParams {
+                        files: vec![lsp_types::FileCreate { uri: uri.into() }],
+                    },
+                })
+            }
+
+            (Operation::Remove { .. }, [deleted]) => {
+                let uri = path_uri(cwd, &deleted.path, false, false);
+                matches(&ops.did_delete, &uri, false).then(|| lsp::Notification::DidDeleteFiles {
+                    params: lsp_types::DeleteFilesParams {
+                        files: vec![lsp_types::FileDelete { uri: uri.into() }],
+                    },
+                })
+            }
+
+            (Operation::Rename { .. }, [from, to]) => {
+                let old_uri = path_uri(cwd, &from.path, from.is_dir, false);
+                let new_uri = path_uri(cwd, &to.path, to.is_dir, false);
+                matches(&ops.did_rename, &old_uri, from.is_dir).then(|| {
+                    lsp::Notification::DidRenameFiles {
+                        params: lsp_types::RenameFilesParams {
+                            files: vec![lsp_types::FileRename {
+                                old_uri: old_uri.into(),
+                                new_uri: new_uri.into(),
+                            }],
+                        },
+                    }
+                })
+            }
+
+            _ => None,
+        };
+        if let Some(notification) = notification {
+            self.handle.notify(notification).await;
+        }
+    }
+
+    /// Send `workspace/didChangeWatchedFiles`.
+    /// Returns `false` if the session is gone.
+    async fn did_change_watched_files(&self, cwd: &Path, changes: &[Change]) -> bool {
+        if changes.is_empty() {
+            return true;
+        }
+        let notification = lsp::Notification::DidChangeWatchedFiles {
+            params: lsp_types::DidChangeWatchedFilesParams {
+                changes: changes.iter().map(|c| c.event(cwd, false)).collect(),
+            },
+        };
+        self.handle.notify(notification).await
+    }
+}
+
+/// Returns `true` if `uri` matches any of the filters the server registered with.
+fn matches(options: &Option<FileOperationRegistrationOptions>, uri: &Url, is_dir: bool) -> bool {
+    let options = match options {
+        Some(options) => options,
+        None => return false,
+    };
+    let path = match uri.to_file_path() {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    options.filters.iter().any(|filter| {
+        let pattern = &filter.pattern;
+        let kind_matches = match &pattern.matches {
+            Some(FileOperationPatternKind::File) => !is_dir,
+            Some(FileOperationPatternKind::Folder) => is_dir,
+            None => true,
+        };
+        let ignore_case = pattern
+            .options
+            .as_ref()
+            .and_then(|o| o.ignore_case)
+            .unwrap_or(false);
+        filter.scheme.as_deref().is_none_or(|s| s == uri.scheme())
+            && kind_matches
+            && GlobBuilder::new(&pattern.glob)
+                .literal_separator(true)
+                .case_insensitive(ignore_case)
+                .build()
+                .map(|glob| glob.compile_matcher().is_match(&path))
+                .unwrap_or(false)
+    })
+}
+
+#[test]
+fn test_matches() {
+    let options: Option<FileOperationRegistrationOptions> =
+        serde_json::from_value(serde_json::json!({
+            "filters": [
+                {"scheme": "file", "pattern": {"glob": "**/*.java", "matches": "file"}},
+                {"pattern": {"glob": "**/src/**", "matches": "folder", "options": {"ignoreCase": true}}},
+            ],
+        }))
+        .unwrap();
+    let uri = |path: &str| Url::parse(&format!("file://{}", path)).unwrap();
+    assert!(matches(&options, &uri("/ws/src/foo/Bar.java"), false));
+    assert!(!matches(&options, &uri("/ws/lib/Bar.java"), true));
+    assert!(!matches(&options, &uri("/ws/src/foo/Bar.kt"), false));
+    assert!(matches(&options, &uri("/ws/SRC/foo/"), true));
+    assert!(!matches(&None, &uri("/ws/src/foo/Bar.java"), false));
 }
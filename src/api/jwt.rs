@@ -0,0 +1,470 @@
+//! JSON Web Tokens signed with RS256 or HS256, verified with keys from a JWKS file.
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::JwtConfig;
+
+use super::auth::{check_expiration, now, Claims, Unauthorized, Verifier};
+
+/// Verifies JWTs with keys from the JWKS file.
+#[derive(Debug)]
+pub struct Jwt {
+    keys: Vec<Key>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway_secs: u64,
+}
+
+struct Key {
+    kid: Option<String>,
+    kind: KeyKind,
+}
+
+enum KeyKind {
+    /// RSA public key for RS256.
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    /// Symmetric key for HS256.
+    Oct(Vec<u8>),
+}
+
+// Manually implemented to avoid showing the symmetric keys.
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kty = match self.kind {
+            KeyKind::Rsa { .. } => "RSA",
+            KeyKind::Oct(_) => "oct",
+        };
+        f.debug_struct("Key")
+            .field("kid", &self.kid)
+            .field("kty", &kty)
+            .finish()
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(serde::Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    #[serde(rename = "use")]
+    use_: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    k: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// Registered claims checked in addition to `exp`.
+#[derive(serde::Deserialize)]
+struct Registered {
+    nbf: Option<u64>,
+    iss: Option<String>,
+    aud: Option<Audience>,
+    #[serde(flatten)]
+    claims: Claims,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == audience,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == audience),
+        }
+    }
+}
+
+impl Jwt {
+    pub fn new(config: &JwtConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let jwks = std::fs::read_to_string(&config.jwks_path)
+            .map_err(|e| format!("failed to read JWKS {}: {}", config.jwks_path, e))?;
+        let jwks: Jwks = serde_json::from_str(&jwks)
+            .map_err(|e| format!("invalid JWKS {}: {}", config.jwks_path, e))?;
+        let keys = jwks
+            .keys
+            .into_iter()
+            .filter(|jwk| jwk.use_.as_deref().is_none_or(|u| u == "sig"))
+            .filter_map(|jwk| match Key::from_jwk(&jwk) {
+                Ok(key) => key,
+                Err(err) => {
+                    tracing::warn!("ignoring key {:?} in JWKS: {}", jwk.kid, err);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(format!("no usable keys in JWKS {}", config.jwks_path).into());
+        }
+        Ok(Self {
+            keys,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            leeway_secs: config.leeway_secs,
+        })
+    }
+
+    fn verify_jwt(
+        &self,
+        header: &str,
+        payload: &str,
+        signature: &str,
+    ) -> Result<Claims, Unauthorized> {
+        let message = format!("{}.{}", header, payload);
+        let header: Header = decode_json(header).ok_or("invalid header")?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| "invalid signature")?;
+        let registered: Registered = decode_json(payload).ok_or("invalid payload")?;
+        // Not verified yet, but useful to tell who's failing.
+        let subject = registered.claims.sub.clone();
+
+        let verified = self
+            .keys
+            .iter()
+            .filter(|key| header.kid.is_none() || key.kid == header.kid)
+            .any(|key| key.verify(&header.alg, message.as_bytes(), &signature));
+        if !verified {
+            return Err(Unauthorized::from("invalid signature").with_subject(subject));
+        }
+
+        let leeway = self.leeway_secs;
+        if registered
+            .nbf
+            .is_some_and(|nbf| nbf > now().saturating_add(leeway))
+        {
+            return Err(Unauthorized::from("token not yet valid").with_subject(subject));
+        }
+        if let Some(issuer) = &self.issuer {
+            if registered.iss.as_ref() != Some(issuer) {
+                return Err(Unauthorized::from("invalid issuer").with_subject(subject));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            if !registered.aud.is_some_and(|aud| aud.contains(audience)) {
+                return Err(Unauthorized::from("invalid audience").with_subject(subject));
+            }
+        }
+        check_expiration(&registered.claims, leeway)?;
+        Ok(registered.claims)
+    }
+}
+
+impl Verifier for Jwt {
+    fn verify(&self, token: &str) -> Option<Result<Claims, Unauthorized>> {
+        let mut parts = token.split('.');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature), None) => {
+                Some(self.verify_jwt(header, payload, signature))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Key {
+    /// Returns `None` for keys of unsupported types.
+    fn from_jwk(jwk: &Jwk) -> Result<Option<Self>, String> {
+        let decode = |v: &Option<String>, name: &str| {
+            let v = v.as_ref().ok_or_else(|| format!("missing {}", name))?;
+            URL_SAFE_NO_PAD
+                .decode(v)
+                .map_err(|_| format!("invalid {}", name))
+        };
+        let kind = match jwk.kty.as_str() {
+            "RSA" => KeyKind::Rsa {
+                n: decode(&jwk.n, "n")?,
+                e: decode(&jwk.e, "e")?,
+            },
+            "oct" => KeyKind::Oct(decode(&jwk.k, "k")?),
+            _ => return Ok(None),
+        };
+        Ok(Some(Self {
+            kid: jwk.kid.clone(),
+            kind,
+        }))
+    }
+
+    /// Verify `signature` of `message`. The algorithm must match the type of the key.
+    fn verify(&self, alg: &str, message: &[u8], signature: &[u8]) -> bool {
+        match (&self.kind, alg) {
+            (KeyKind::Rsa { n, e }, "RS256") => ring::signature::RsaPublicKeyComponents { n, e }
+                .verify(
+                    &ring::signature::RSA_PKCS1_2048_8192_SHA256,
+                    message,
+                    signature,
+                )
+                .is_ok(),
+            (KeyKind::Oct(secret), "HS256") => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key size");
+                mac.update(message);
+                mac.verify_slice(signature).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(part: &str) -> Option<T> {
+    let bytes = URL_SAFE_NO_PAD.decode(part).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(value: serde_json::Value) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&value).unwrap())
+    }
+
+    fn hs256(secret: &[u8], header: serde_json::Value, claims: serde_json::Value) -> String {
+        let message = format!("{}.{}", encode(header), encode(claims));
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(message.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}", message, signature)
+    }
+
+    fn jwt() -> Jwt {
+        Jwt {
+            keys: vec![Key {
+                kid: Some("a".to_owned()),
+                kind: KeyKind::Oct(b"secret".to_vec()),
+            }],
+            issuer: Some("app".to_owned()),
+            audience: Some("lsp".to_owned()),
+            leeway_secs: 0,
+        }
+    }
+
+    #[test]
+    fn test_hs256() {
+        let jwt = jwt();
+        let header = serde_json::json!({"alg": "HS256", "kid": "a"});
+        let claims = serde_json::json!({
+            "sub": "alice",
+            "iss": "app",
+            "aud": ["lsp", "other"],
+            "exp": now() + 60,
+            "servers": ["rust"],
+            "read_only": true,
+        });
+        let token = hs256(b"secret", header.clone(), claims.clone());
+        let verified = jwt.verify(&token).unwrap().unwrap();
+        assert_eq!(verified.sub.as_deref(), Some("alice"));
+        assert!(verified.read_only);
+        assert!(!verified.allows_server("python"));
+
+        // Wrong key
+        let token = hs256(b"other", header.clone(), claims.clone());
+        let err = jwt.verify(&token).unwrap().unwrap_err();
+        assert_eq!(err.reason, "invalid signature");
+        assert_eq!(err.subject.as_deref(), Some("alice"));
+        // RS256 with the symmetric key
+        let token = hs256(
+            b"secret",
+            serde_json::json!({"alg": "RS256"}),
+            claims.clone(),
+        );
+        assert!(jwt.verify(&token).unwrap().is_err());
+        // Wrong audience
+        let mut other = claims.clone();
+        other["aud"] = "other".into();
+        let token = hs256(b"secret", header.clone(), other);
+        assert_eq!(
+            jwt.verify(&token).unwrap().unwrap_err().reason,
+            "invalid audience"
+        );
+        // Not yet valid
+        let mut early = claims;
+        early["nbf"] = (now() + 60).into();
+        let token = hs256(b"secret", header, early);
+        assert_eq!(
+            jwt.verify(&token).unwrap().unwrap_err().reason,
+            "token not yet valid"
+        );
+        // Not a JWT
+        assert!(jwt.verify("a.b").is_none());
+    }
+
+    fn rsa_key_pair() -> ring::signature::RsaKeyPair {
+        use tokio_rustls::rustls::pki_types::{pem::PemObject, PrivatePkcs8KeyDer};
+
+        let der =
+            PrivatePkcs8KeyDer::from_pem_slice(include_bytes!("testdata/jwt/rsa.pem")).unwrap();
+        ring::signature::RsaKeyPair::from_pkcs8(der.secret_pkcs8_der()).unwrap()
+    }
+
+    fn rs256(
+        key_pair: &ring::signature::RsaKeyPair,
+        header: serde_json::Value,
+        claims: serde_json::Value,
+    ) -> String {
+        let message = format!("{}.{}", encode(header), encode(claims));
+        let mut signature = vec![0; key_pair.public().modulus_len()];
+        key_pair
+            .sign(
+                &ring::signature::RSA_PKCS1_SHA256,
+                &ring::rand::SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+            .unwrap();
+        format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    #[test]
+    fn test_rs256() {
+        let key_pair = rsa_key_pair();
+        let public = ring::signature::RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+        let dir = std::env::temp_dir().join(format!("lsp-ws-proxy-jwt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let jwks_path = dir.join("jwks.json");
+        std::fs::write(
+            &jwks_path,
+            serde_json::json!({"keys": [
+                {
+                    "kty": "RSA",
+                    "kid": "rsa",
+                    "use": "sig",
+                    "n": URL_SAFE_NO_PAD.encode(&public.n),
+                    "e": URL_SAFE_NO_PAD.encode(&public.e),
+                },
+                {"kty": "oct", "kid": "oct", "k": URL_SAFE_NO_PAD.encode(b"secret")},
+                {"kty": "EC", "kid": "ec", "crv": "P-256"},
+            ]})
+            .to_string(),
+        )
+        .unwrap();
+        let jwt = Jwt::new(&JwtConfig {
+            jwks_path: jwks_path.to_string_lossy().into_owned(),
+            issuer: Some("app".to_owned()),
+            audience: Some("lsp".to_owned()),
+            leeway_secs: 0,
+        })
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // The EC key is ignored.
+        assert_eq!(jwt.keys.len(), 2);
+
+        let header = serde_json::json!({"alg": "RS256", "kid": "rsa"});
+        let claims = serde_json::json!({
+            "sub": "alice",
+            "iss": "app",
+            "aud": "lsp",
+            "exp": now() + 60,
+        });
+        let reason = |token: &str| jwt.verify(token).unwrap().unwrap_err().reason;
+        let verified = jwt
+            .verify(&rs256(&key_pair, header.clone(), claims.clone()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(verified.sub.as_deref(), Some("alice"));
+        // Without `kid`, any key of the type can verify.
+        let token = rs256(
+            &key_pair,
+            serde_json::json!({"alg": "RS256"}),
+            claims.clone(),
+        );
+        assert!(jwt.verify(&token).unwrap().is_ok());
+
+        // RS256 token against the symmetric key.
+        let token = rs256(
+            &key_pair,
+            serde_json::json!({"alg": "RS256", "kid": "oct"}),
+            claims.clone(),
+        );
+        assert_eq!(reason(&token), "invalid signature");
+        // HS256 token against the RSA key, signed with the public key as the secret.
+        for secret in [&public.n, &jwks_rsa_json(&public)] {
+            let token = hs256(
+                secret,
+                serde_json::json!({"alg": "HS256", "kid": "rsa"}),
+                claims.clone(),
+            );
+            assert_eq!(reason(&token), "invalid signature");
+        }
+        // HS256 token against the symmetric key is still fine.
+        let token = hs256(
+            b"secret",
+            serde_json::json!({"alg": "HS256", "kid": "oct"}),
+            claims.clone(),
+        );
+        assert!(jwt.verify(&token).unwrap().is_ok());
+        // Unsupported algorithm
+        let token = rs256(
+            &key_pair,
+            serde_json::json!({"alg": "none", "kid": "rsa"}),
+            claims.clone(),
+        );
+        assert_eq!(reason(&token), "invalid signature");
+        // Signed for another `kid`
+        let token = rs256(
+            &key_pair,
+            serde_json::json!({"alg": "RS256", "kid": "other"}),
+            claims.clone(),
+        );
+        assert_eq!(reason(&token), "invalid signature");
+
+        let with = |name: &str, value: serde_json::Value| {
+            let mut claims = claims.clone();
+            match value {
+                serde_json::Value::Null => claims.as_object_mut().unwrap().remove(name),
+                value => claims
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(name.to_owned(), value),
+            };
+            rs256(&key_pair, header.clone(), claims)
+        };
+        assert_eq!(
+            reason(&with("nbf", (now() + 60).into())),
+            "token not yet valid"
+        );
+        assert!(jwt.verify(&with("nbf", now().into())).unwrap().is_ok());
+        assert_eq!(reason(&with("iss", "other".into())), "invalid issuer");
+        assert_eq!(
+            reason(&with("iss", serde_json::Value::Null)),
+            "invalid issuer"
+        );
+        assert_eq!(
+            reason(&with("aud", serde_json::json!(["other"]))),
+            "invalid audience"
+        );
+        assert_eq!(
+            reason(&with("aud", serde_json::Value::Null)),
+            "invalid audience"
+        );
+        assert!(jwt
+            .verify(&with("exp", (now() - 1).into()))
+            .unwrap()
+            .is_err());
+    }
+
+    /// The RSA key as in the JWKS, another guess of the secret for algorithm confusion.
+    fn jwks_rsa_json(public: &ring::signature::RsaPublicKeyComponents<Vec<u8>>) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "kty": "RSA",
+            "n": URL_SAFE_NO_PAD.encode(&public.n),
+            "e": URL_SAFE_NO_PAD.encode(&public.e),
+        }))
+        .unwrap()
+    }
+}
@@ -0,0 +1,233 @@
+//! Resource limits of Language Servers.
+use std::{
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::Duration,
+};
+
+use tokio::process::Command;
+
+use crate::config::LimitsConfig;
+
+/// cgroup v2 created for a server process. Removed when dropped.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    // `cgroup.procs` opened before spawning, so that the `pre_exec` hook
+    // can move the process without allocating.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    procs: std::fs::File,
+}
+
+impl Cgroup {
+    fn create(parent: &str, limits: &LimitsConfig) -> Result<Self, std::io::Error> {
+        let path = Path::new(parent).join(format!("lsp-ws-proxy-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&path)?;
+        let procs = match open_procs(&path, limits) {
+            Ok(procs) => procs,
+            Err(err) => {
+                let _ = std::fs::remove_dir(&path);
+                return Err(err);
+            }
+        };
+        Ok(Self { path, procs })
+    }
+
+    /// Returns `true` if any process in the cgroup was killed for exceeding `memory.max`.
+    fn oom_killed(&self) -> bool {
+        std::fs::read_to_string(self.path.join("memory.events")).is_ok_and(|events| {
+            events
+                .lines()
+                .any(|line| matches!(line.split_once(' '), Some(("oom_kill", n)) if n != "0"))
+        })
+    }
+}
+
+fn open_procs(path: &Path, limits: &LimitsConfig) -> Result<std::fs::File, std::io::Error> {
+    if let Some(bytes) = limits.memory_max_bytes {
+        std::fs::write(path.join("memory.max"), bytes.to_string())?;
+    }
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path.join("cgroup.procs"))
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // The cgroup can't be removed until the processes exit, so keep trying in the background.
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(remove_cgroup(std::mem::take(&mut self.path)));
+        }
+    }
+}
+
+async fn remove_cgroup(path: PathBuf) {
+    // Kill any processes left behind by the server. Requires Linux 5.14.
+    let _ = tokio::fs::write(path.join("cgroup.kill"), "1").await;
+    for _ in 0..20 {
+        if tokio::fs::remove_dir(&path).await.is_ok() {
+            tracing::debug!("removed cgroup {:?}", path);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    tracing::warn!("failed to remove cgroup {:?}", path);
+}
+
+/// Apply `limits` to the process spawned by `command`.
+/// Returns the cgroup the process will be placed in if configured.
+#[cfg(unix)]
+pub fn apply(
+    command: &mut Command,
+    limits: &LimitsConfig,
+) -> Result<Option<Cgroup>, std::io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    type Resource = libc::__rlimit_resource_t;
+    #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+    type Resource = libc::c_int;
+
+    fn check(ret: libc::c_int) -> Result<(), std::io::Error> {
+        if ret < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set(resource: Resource, value: Option<u64>, extra: u64) -> Result<(), std::io::Error> {
+        if let Some(value) = value {
+            let limit = libc::rlimit {
+                rlim_cur: value as libc::rlim_t,
+                rlim_max: value.saturating_add(extra) as libc::rlim_t,
+            };
+            check(unsafe { libc::setrlimit(resource, &limit) })?;
+        }
+        Ok(())
+    }
+
+    let cgroup = limits
+        .cgroup
+        .as_ref()
+        .map(|parent| Cgroup::create(parent, limits))
+        .transpose()?;
+    let procs = cgroup.as_ref().map(|c| c.procs.as_raw_fd());
+    let limits = limits.clone();
+    // SAFETY: Only async-signal-safe functions are called after fork.
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(fd) = procs {
+                // Writing 0 moves the writing process.
+                check(libc::write(fd, b"0".as_ptr().cast(), 1) as libc::c_int)?;
+            }
+            set(libc::RLIMIT_AS, limits.address_space_bytes, 0)?;
+            // Raise the hard limit by a second so that `SIGXCPU` is sent before `SIGKILL`.
+            set(libc::RLIMIT_CPU, limits.cpu_secs, 1)?;
+            set(libc::RLIMIT_NOFILE, limits.open_files, 0)?;
+            set(libc::RLIMIT_NPROC, limits.processes, 0)?;
+            if let Some(nice) = limits.nice {
+                check(libc::setpriority(libc::PRIO_PROCESS, 0, nice))?;
+            }
+            Ok(())
+        });
+    }
+    Ok(cgroup)
+}
+
+#[cfg(not(unix))]
+pub fn apply(
+    _command: &mut Command,
+    _limits: &LimitsConfig,
+) -> Result<Option<Cgroup>, std::io::Error> {
+    tracing::warn!("resource limits are not supported on this platform");
+    Ok(None)
+}
+
+/// Describe the limit the server exceeded from its exit status, if any.
+pub fn exceeded(
+    status: ExitStatus,
+    limits: &LimitsConfig,
+    cgroup: Option<&Cgroup>,
+) -> Option<String> {
+    if cgroup.is_some_and(Cgroup::oom_killed) {
+        return Some(match limits.memory_max_bytes {
+            Some(bytes) => format!(
+                "Language server was killed for exceeding the memory limit of {} bytes",
+                bytes
+            ),
+            None => "Language server was killed for running out of memory".to_owned(),
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        if let (Some(libc::SIGXCPU), Some(secs)) = (status.signal(), limits.cpu_secs) {
+            return Some(format!(
+                "Language server was killed for exceeding the CPU time limit of {}s",
+                secs
+            ));
+        }
+    }
+
+    match limits.address_space_bytes {
+        // Failing to allocate doesn't leave a trace, but it usually ends with an abort
+        // or a segfault, so this is the best we can do.
+        Some(bytes) if failed_to_allocate(status) => Some(format!(
+            "Language server exited ({}) while limited to {} bytes of address space, \
+             it may have run out of memory",
+            status, bytes
+        )),
+        Some(_) if !status.success() => Some(format!("Language server exited ({})", status)),
+        _ => None,
+    }
+}
+
+/// Returns `true` if the process was killed by a signal raised on failed allocation.
+#[cfg(unix)]
+fn failed_to_allocate(status: ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+
+    matches!(status.signal(), Some(libc::SIGABRT | libc::SIGSEGV))
+}
+
+#[cfg(not(unix))]
+fn failed_to_allocate(_status: ExitStatus) -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    #[test]
+    fn test_exceeded() {
+        let limits: LimitsConfig = serde_json::from_value(serde_json::json!({
+            "cpu_secs": 10,
+            "address_space_bytes": 1024,
+        }))
+        .unwrap();
+        // Signaled with SIGXCPU
+        let status = ExitStatus::from_raw(libc::SIGXCPU);
+        assert_eq!(
+            exceeded(status, &limits, None).unwrap(),
+            "Language server was killed for exceeding the CPU time limit of 10s"
+        );
+        // Aborted
+        let status = ExitStatus::from_raw(libc::SIGABRT);
+        assert!(exceeded(status, &limits, None)
+            .unwrap()
+            .contains("1024 bytes of address space"));
+        // Exited with 1
+        let status = ExitStatus::from_raw(1 << 8);
+        assert_eq!(
+            exceeded(status, &limits, None).unwrap(),
+            "Language server exited (exit status: 1)"
+        );
+        assert_eq!(exceeded(ExitStatus::from_raw(0), &limits, None), None);
+    }
+}
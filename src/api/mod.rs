@@ -2,8 +2,23 @@ use std::{convert::Infallible, error::Error};
 
 use warp::{http::StatusCode, reply, Filter, Rejection, Reply};
 
+pub mod admission;
+pub mod auth;
+pub mod cors;
+mod edit;
 pub mod files;
+mod jwt;
+mod limits;
+pub mod pool;
 pub mod proxy;
+pub mod rate_limit;
+pub mod reload;
+pub mod server;
+pub mod session;
+pub mod shutdown;
+mod stderr;
+pub mod tls;
+pub mod workspace;
 
 fn with_context<T>(ctx: T) -> impl Filter<Extract = (T,), Error = Infallible> + Clone
 where
@@ -28,6 +43,15 @@ fn json_response<T: serde::Serialize>(res: &T, status: StatusCode) -> reply::Res
 pub async fn recover(err: Rejection) -> Result<impl Reply, Rejection> {
     let (reason, status) = if err.is_not_found() {
         ("Not Found", StatusCode::NOT_FOUND)
+    } else if let Some(auth::Unauthorized { reason, .. }) = err.find() {
+        return Ok(json_error_response(
+            reason.clone(),
+            StatusCode::UNAUTHORIZED,
+        ));
+    } else if err.find::<cors::OriginNotAllowed>().is_some() {
+        ("Origin Not Allowed", StatusCode::FORBIDDEN)
+    } else if err.find::<rate_limit::TooManyRequests>().is_some() {
+        ("Too Many Requests", StatusCode::TOO_MANY_REQUESTS)
     } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
         if let Some(cause) = e.source() {
             tracing::debug!("deserialize error: {:?}", cause);
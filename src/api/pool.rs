@@ -0,0 +1,243 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Mutex, Notify};
+use url::Url;
+
+use crate::{
+    config::{Config, PoolConfig, ServerConfig},
+    lsp::{self, types::Id},
+};
+
+use super::server::{Launch, Placeholders, Server, INITIALIZE_TIMEOUT};
+
+/// ID used for the `initialize` request sent to pooled servers.
+const POOL_INITIALIZE_ID: &str = "lsp-ws-proxy/pool/initialize";
+
+/// How long to wait before trying again after failing to spawn.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Warm pools of Language Servers keyed by the server name.
+#[derive(Debug, Default)]
+pub struct Pool {
+    pools: HashMap<String, Arc<ServerPool>>,
+}
+
+impl Pool {
+    /// Create pools for servers with `pool` configured, and start filling them in the background.
+    pub fn new(config: &Config, cwd: &Url) -> Self {
+        let mut pools = HashMap::new();
+        // Pooled servers would be started outside of the workspace of the session.
+        if config.workspace.is_some() {
+            if config
+                .servers
+                .iter()
+                .flatten()
+                .any(|(_, s)| s.pool.is_some())
+            {
+                tracing::warn!("pools are disabled when workspaces are isolated");
+            }
+            return Self { pools };
+        }
+        let workspace = cwd.to_file_path().expect("valid path from cwd");
+        for (name, server) in config.servers.iter().flatten() {
+            if let Some(pool_config) = &server.pool {
+                // `${query.<name>}` is not available without a connection, so spawning would
+                // fail every time.
+                let placeholders = Placeholders {
+                    session_id: "",
+                    workspace: &workspace.to_string_lossy(),
+                    query: &HashMap::new(),
+                };
+                if let Err(err) = Launch::new(name, server, &placeholders) {
+                    tracing::warn!("{}: pool is disabled: {}", name, err);
+                    continue;
+                }
+                let pool = Arc::new(ServerPool {
+                    name: name.clone(),
+                    server: server.clone(),
+                    workspace: workspace.clone(),
+                    config: pool_config.clone(),
+                    initialize: pool_config.pre_initialize.then(|| {
+                        pool_config.initialize_params.clone().unwrap_or_else(|| {
+                            serde_json::json!({
+                                "processId": null,
+                                "rootUri": cwd,
+                                "capabilities": {},
+                            })
+                        })
+                    }),
+                    ready: Mutex::new(VecDeque::new()),
+                    refill: Notify::new(),
+                });
+                tokio::spawn(pool.clone().run());
+                pools.insert(name.clone(), pool);
+            }
+        }
+        Self { pools }
+    }
+
+    /// Take a ready server for `name` if available.
+    pub async fn take(&self, name: &str) -> Option<Warm> {
+        let pool = self.pools.get(name)?;
+        let warm = pool.take().await;
+        pool.refill.notify_one();
+        warm
+    }
+}
+
+/// A server spawned ahead of the connection.
+#[derive(Debug)]
+pub struct Warm {
+    /// How `server` was started, with the `${session_id}` of the pool.
+    pub launch: Launch,
+    pub server: Server,
+    /// Set if the server was pre-initialized.
+    pub initialized: Option<PreInitialized>,
+    idle_since: Instant,
+}
+
+/// The handshake done with a pre-initialized server.
+#[derive(Debug)]
+pub struct PreInitialized {
+    /// `initialize` request sent to the server.
+    pub request: lsp::Request,
+    /// `InitializeResult` from the server.
+    pub result: serde_json::Value,
+}
+
+#[derive(Debug)]
+struct ServerPool {
+    name: String,
+    server: ServerConfig,
+    /// Value of `${workspace}`.
+    workspace: PathBuf,
+    config: PoolConfig,
+    /// `initialize` params if pre-initialization is enabled.
+    initialize: Option<serde_json::Value>,
+    ready: Mutex<VecDeque<Warm>>,
+    refill: Notify,
+}
+
+impl ServerPool {
+    async fn take(&self) -> Option<Warm> {
+        let mut ready = self.ready.lock().await;
+        while let Some(warm) = ready.pop_front() {
+            if !self.is_expired(&warm) {
+                return Some(warm);
+            }
+        }
+        None
+    }
+
+    fn is_expired(&self, warm: &Warm) -> bool {
+        self.config
+            .idle_ttl_secs
+            .is_some_and(|ttl| warm.idle_since.elapsed() >= Duration::from_secs(ttl))
+    }
+
+    /// Keep the pool filled, replacing expired servers.
+    async fn run(self: Arc<Self>) {
+        loop {
+            {
+                let mut ready = self.ready.lock().await;
+                let before = ready.len();
+                ready.retain(|warm| !self.is_expired(warm));
+                if ready.len() < before {
+                    tracing::debug!(
+                        "{}: dropped {} idle servers",
+                        self.name,
+                        before - ready.len()
+                    );
+                }
+            }
+
+            let mut failed = false;
+            while self.ready.lock().await.len() < self.config.size {
+                match self.spawn().await {
+                    Ok(warm) => self.ready.lock().await.push_back(warm),
+                    Err(err) => {
+                        tracing::error!("{}: failed to start pooled server: {}", self.name, err);
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+
+            let wait = if failed {
+                RETRY_DELAY
+            } else {
+                self.config
+                    .idle_ttl_secs
+                    .map_or(Duration::from_secs(3600), Duration::from_secs)
+            };
+            tokio::select! {
+                _ = self.refill.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+
+    async fn spawn(&self) -> Result<Warm, Box<dyn std::error::Error + Send + Sync>> {
+        // Pooled servers are started before the connection, so `${session_id}`
+        // is unique for each process and `${query.<name>}` is not available.
+        let id = uuid::Uuid::new_v4().to_string();
+        let workspace = self
+            .workspace
+            .to_str()
+            .ok_or_else(|| format!("workspace path {:?} is not valid UTF-8", self.workspace))?;
+        let launch = Launch::new(
+            &self.name,
+            &self.server,
+            &Placeholders {
+                session_id: &id,
+                workspace,
+                query: &HashMap::new(),
+            },
+        )?;
+        tracing::debug!("{}: starting pooled {}", self.name, launch.command[0]);
+        let mut server = Server::spawn(&launch)?;
+        let initialized = if let Some(params) = &self.initialize {
+            let request = lsp::Request::Initialize {
+                id: Id::String(POOL_INITIALIZE_ID.to_owned()),
+                params: serde_json::from_value(params.clone())?,
+            };
+            let result = tokio::time::timeout(INITIALIZE_TIMEOUT, server.initialize(&request))
+                .await
+                .map_err(|_| "timed out waiting for initialize response")??;
+            Some(PreInitialized { request, result })
+        } else {
+            None
+        };
+        Ok(Warm {
+            launch,
+            server,
+            initialized,
+            idle_since: Instant::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disable_pool_with_query_placeholders() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "servers": {
+                "ls": {
+                    "command": ["ls", "--project", "${query.project}"],
+                    "pool": {"size": 1},
+                },
+            },
+        }))
+        .unwrap();
+        let cwd = Url::from_directory_path(std::env::temp_dir()).unwrap();
+        assert!(Pool::new(&config, &cwd).pools.is_empty());
+    }
+}
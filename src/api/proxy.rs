@@ -1,13 +1,43 @@
-use std::{convert::Infallible, process::Stdio, str::FromStr};
+use std::{collections::HashMap, convert::Infallible, str::FromStr, sync::Arc, time::Duration};
 
-use futures_util::{stream, SinkExt, StreamExt};
-use tokio::{fs, process::Command};
+use futures_util::{stream, stream::SplitSink, Sink, SinkExt, StreamExt};
+use tokio::{fs, sync::watch};
 use url::Url;
-use warp::{Filter, Rejection, Reply};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
 
-use crate::{config::Config, lsp};
+use crate::{
+    config::{Config, ServerConfig},
+    lsp::{
+        self,
+        ext::proxy::{QueueParams, ServerRestartedParams},
+        types::Id,
+    },
+};
 
-use super::with_context;
+use super::{
+    admission::{Admission, Admitted, Entry, Ticket},
+    auth::{self, Auth, Claims},
+    cors::{self, Cors},
+    json_error_response,
+    pool::{Pool, PreInitialized},
+    rate_limit::MessageLimiter,
+    server::{Launch, Placeholders, Server, INITIALIZE_TIMEOUT, SHUTDOWN_TIMEOUT},
+    session::{Batched, ProxyMessage, Session, Sessions},
+    shutdown::Shutdown,
+    with_context,
+    workspace::{Workspace, Workspaces},
+};
+
+type ClientSink = SplitSink<warp::ws::WebSocket, warp::ws::Message>;
+
+/// Close code sent when the connection timed out waiting in the queue (Try Again Later).
+const CLOSE_QUEUE_TIMEOUT: u16 = 1013;
+/// Close code sent when the proxy is shutting down (Going Away).
+const CLOSE_SHUTDOWN: u16 = 1001;
+/// Close code sent when the session was closed for being idle.
+const CLOSE_IDLE: u16 = 4000;
+/// Close code sent when the session reached the maximum duration allowed by the token.
+const CLOSE_EXPIRED: u16 = 4001;
 
 #[derive(Debug, Clone)]
 pub struct Context {
@@ -21,15 +51,60 @@ pub struct Context {
     pub remap: bool,
     /// Project root.
     pub cwd: Url,
-    /// config
-    pub config: Option<Config>,
+    /// Config of the connection. The latest one when connected.
+    pub config: Option<Arc<Config>>,
+    /// Receives the config reloaded from the file if set.
+    pub config_updates: Option<watch::Receiver<Arc<Config>>>,
+    /// Warm pools of servers from `config`.
+    pub pool: Option<Arc<Pool>>,
+    /// Connected sessions reachable from `/files`, and detached sessions that can be resumed.
+    pub sessions: Arc<Sessions>,
+    /// Create a workspace for each session instead of using `cwd` if set.
+    pub workspaces: Option<Arc<Workspaces>>,
+    /// Limits of concurrent connections and servers.
+    pub admission: Arc<Admission>,
+    /// Tells sessions to shut down when the proxy is stopping.
+    pub shutdown: Arc<Shutdown>,
+    /// Require a token to connect if set.
+    pub auth: Option<Arc<Auth>>,
+    /// Origins allowed to send requests from browsers.
+    pub cors: Arc<Cors>,
 }
 
-#[derive(Clone, Debug, serde::Deserialize)]
+impl Context {
+    /// Use the latest config for a new connection.
+    fn with_latest_config(mut self) -> Self {
+        if let Some(updates) = &self.config_updates {
+            self.config = Some(updates.borrow().clone());
+        }
+        self
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
 struct Query {
     /// The command name of the Language Server to start.
     /// If not specified, the first one is started.
-    name: String,
+    name: Option<String>,
+    /// Token of the detached session to resume.
+    session: Option<String>,
+    /// Token to authenticate with. Read by `auth::with_claims`, declared to keep it out of `params`.
+    token: Option<String>,
+    /// Any other parameters for `${query.<name>}` placeholders.
+    #[serde(flatten)]
+    params: HashMap<String, String>,
+}
+
+// Manually implemented to avoid showing the tokens.
+impl std::fmt::Debug for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Query")
+            .field("name", &self.name)
+            .field("session", &self.session.as_ref().map(|_| ".."))
+            .field("token", &self.token.as_ref().map(|_| ".."))
+            .field("params", &self.params)
+            .finish()
+    }
 }
 
 fn with_optional_query() -> impl Filter<Extract = (Option<Query>,), Error = Infallible> + Clone {
@@ -42,21 +117,56 @@ fn with_optional_query() -> impl Filter<Extract = (Option<Query>,), Error = Infa
 pub fn handler(ctx: Context) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::path::end()
         .and(warp::ws())
+        .and(cors::check_origin(ctx.cors.clone()))
+        .and(auth::with_claims(ctx.auth.clone()))
         .and(with_context(ctx))
         .and(with_optional_query())
-        .map(|ws: warp::ws::Ws, ctx, query| {
-            ws.with_compression()
-                .on_upgrade(move |socket| on_upgrade(socket, ctx, query))
-        })
+        .map(
+            |ws: warp::ws::Ws, claims: Claims, ctx: Context, query: Option<Query>| {
+                let ctx = ctx.with_latest_config();
+                let entry = match ctx.admission.enter(limited_instance(&ctx, &query)) {
+                    Some(entry) => entry,
+                    None => {
+                        tracing::warn!("rejecting connection over the limits");
+                        return json_error_response(
+                            "too many connections",
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        );
+                    }
+                };
+                ws.with_compression()
+                    .on_upgrade(move |socket| on_upgrade(socket, ctx, query, claims, entry))
+                    .into_response()
+            },
+        )
+}
+
+/// Name of the server to start if its instances are limited.
+fn limited_instance(ctx: &Context, query: &Option<Query>) -> Option<String> {
+    // Resuming sessions already have the server running.
+    if query.as_ref().is_some_and(|q| q.session.is_some()) {
+        return None;
+    }
+    // The name in the query falls back to the first command when missing or unknown.
+    let (name, _) = find_command(ctx, query)?;
+    ctx.admission.limits_instances(&name).then_some(name)
 }
 
-#[tracing::instrument(level = "debug", err, skip(msg))]
-async fn maybe_write_text_document(msg: &lsp::Message) -> Result<(), std::io::Error> {
+#[tracing::instrument(level = "debug", err, skip(msg, workspace))]
+async fn maybe_write_text_document(
+    msg: &lsp::Message,
+    workspace: &Workspace,
+) -> Result<(), std::io::Error> {
     if let lsp::Message::Notification(lsp::Notification::DidSave { params }) = msg {
         if let Some(text) = &params.text {
             let uri = &params.text_document.uri;
             if uri.scheme() == "file" {
                 if let Ok(path) = uri.to_file_path() {
+                    // Don't let the session write to other workspaces.
+                    if workspace.is_isolated() && !path.starts_with(&workspace.path) {
+                        tracing::warn!("not writing {:?} outside of the workspace", path);
+                        return Ok(());
+                    }
                     if let Some(parent) = path.parent() {
                         tracing::debug!("writing to {:?}", path);
                         fs::create_dir_all(parent).await?;
@@ -69,62 +179,282 @@ async fn maybe_write_text_document(msg: &lsp::Message) -> Result<(), std::io::Er
     Ok(())
 }
 
-async fn on_upgrade(socket: warp::ws::WebSocket, ctx: Context, query: Option<Query>) {
+async fn on_upgrade(
+    socket: warp::ws::WebSocket,
+    ctx: Context,
+    query: Option<Query>,
+    claims: Claims,
+    entry: Entry,
+) {
     tracing::info!("connected");
-    if let Err(err) = connected(socket, ctx, query).await {
+    if let Err(err) = connected(socket, ctx, query, claims, entry).await {
         tracing::error!("connection error: {}", err);
     }
     tracing::info!("disconnected");
 }
 
-fn get_command<'a>(ctx: &'a Context, query: &'a Option<Query>) -> Option<&'a Vec<String>> {
-    if let Some(query) = query {
+/// Get the name and the config of the server to start if allowed by `claims`.
+fn get_command(
+    ctx: &Context,
+    query: &Option<Query>,
+    claims: &Claims,
+) -> Option<(String, ServerConfig)> {
+    let (name, config) = find_command(ctx, query)?;
+    if !claims.allows_server(&name) {
+        tracing::warn!(
+            subject = claims.subject(),
+            "the token does not allow starting {:?}",
+            name
+        );
+        return None;
+    }
+    Some((name, config))
+}
+
+fn find_command(ctx: &Context, query: &Option<Query>) -> Option<(String, ServerConfig)> {
+    if let Some(name) = query.as_ref().and_then(|q| q.name.as_ref()) {
         if let Some(config) = &ctx.config {
             if let Some(servers) = &config.servers {
-                if let Some(sc) = servers.get(&query.name) {
-                    return Some(&sc.command);
+                if let Some(sc) = servers.get(name) {
+                    return Some((name.clone(), sc.clone()));
                 }
             }
         }
         if let Some(command) = ctx
             .commands
             .as_ref()
-            .and_then(|c| c.iter().find(|v| v[0] == query.name))
+            .and_then(|c| c.iter().find(|v| &v[0] == name))
         {
-            Some(command)
+            Some((name.clone(), ServerConfig::from_command(command.clone())))
         } else {
             let not_found_error = &ctx.config.as_ref().map_or(false, |c| c.not_found_error);
             if *not_found_error {
                 None
             } else {
-                tracing::warn!("no command found for {:?}, using the first one", query);
-                ctx.commands.as_ref().and_then(|c| c.first())
+                tracing::warn!("no command found for {:?}, using the first one", name);
+                first_command(ctx)
             }
         }
     } else {
-        ctx.commands.as_ref().and_then(|c| c.first())
+        first_command(ctx)
+    }
+}
+
+fn first_command(ctx: &Context) -> Option<(String, ServerConfig)> {
+    ctx.commands
+        .as_ref()
+        .and_then(|c| c.first())
+        .map(|c| (c[0].clone(), ServerConfig::from_command(c.clone())))
+}
+
+/// Start the server for a new session.
+async fn start_session(
+    ctx: &Context,
+    query: &Option<Query>,
+    claims: &Claims,
+) -> Result<Session, Box<dyn std::error::Error + Send + Sync>> {
+    let (name, server_config) = get_command(ctx, query, claims)
+        .ok_or_else(|| format!("no command found for {:?}", query))?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let workspace = match (&ctx.workspaces, &claims.workspace) {
+        (Some(workspaces), name) => {
+            workspaces
+                .create(&id, name.as_deref().unwrap_or(&id))
+                .await?
+        }
+        (None, None) => Workspace::shared(&ctx.cwd),
+        (None, Some(_)) => {
+            return Err("the token is limited to a workspace, but workspaces are shared".into())
+        }
+    };
+    let (launch, server, pre_initialized) =
+        match start_server(ctx, query, &id, &name, &server_config, &workspace).await {
+            Ok(started) => started,
+            Err(err) => {
+                workspace.cleanup().await?;
+                return Err(err);
+            }
+        };
+
+    let token = ctx
+        .config
+        .as_ref()
+        .and_then(|c| c.resume.as_ref())
+        .map(|_| uuid::Uuid::new_v4().to_string());
+    let mut session = Session::new(
+        id,
+        server_config,
+        launch,
+        server,
+        workspace,
+        token,
+        ctx.shutdown.track(),
+    );
+    if let Some(pre) = pre_initialized {
+        if let Some(replay) = &mut session.replay {
+            replay.set_initialize(pre.request);
+        }
+        session.set_initialize_result(pre.result);
     }
+    Ok(session)
 }
 
-#[tracing::instrument(level = "debug", skip(ws, ctx), fields(remap = %ctx.remap, sync = %ctx.sync))]
+/// Take the server from the pool, or spawn one in `workspace`.
+async fn start_server(
+    ctx: &Context,
+    query: &Option<Query>,
+    id: &str,
+    name: &str,
+    server_config: &ServerConfig,
+    workspace: &Workspace,
+) -> Result<(Launch, Server, Option<PreInitialized>), Box<dyn std::error::Error + Send + Sync>> {
+    let no_params = HashMap::new();
+    let workspace_path = workspace
+        .path
+        .to_str()
+        .ok_or_else(|| format!("workspace path {:?} is not valid UTF-8", workspace.path))?;
+    let launch = Launch::new(
+        name,
+        server_config,
+        &Placeholders {
+            session_id: id,
+            workspace: workspace_path,
+            query: query.as_ref().map_or(&no_params, |q| &q.params),
+        },
+    )?;
+    let warm = match &ctx.pool {
+        Some(pool) => pool.take(name).await,
+        None => None,
+    };
+    // The pooled server was launched with its own `${session_id}`, so keep its launch to
+    // restart it the same way.
+    let (launch, server, pre_initialized) = if let Some(warm) = warm {
+        tracing::info!("using pooled {}", warm.launch.command[0]);
+        (warm.launch, warm.server, warm.initialized)
+    } else {
+        tracing::info!("starting {} in {}", launch.command[0], workspace.uri);
+        let server = Server::spawn(&launch)?;
+        (launch, server, None)
+    };
+    tracing::debug!("running {}", launch.command[0]);
+    Ok((launch, server, pre_initialized))
+}
+
+#[tracing::instrument(level = "debug", skip(ws, ctx, claims, entry), fields(remap = %ctx.remap, sync = %ctx.sync, session, subject))]
 async fn connected(
-    ws: warp::ws::WebSocket,
-    ctx: Context,
+    mut ws: warp::ws::WebSocket,
+    mut ctx: Context,
     query: Option<Query>,
+    claims: Claims,
+    entry: Entry,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let command =
-        get_command(&ctx, &query).ok_or_else(|| format!("no command found for {:?}", query))?;
-    tracing::info!("starting {} in {}", command[0], ctx.cwd);
-    let mut server = Command::new(&command[0])
-        .args(&command[1..])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .kill_on_drop(true)
-        .spawn()?;
-    tracing::debug!("running {}", command[0]);
-
-    let mut server_send = lsp::framed::writer(server.stdin.take().unwrap());
-    let mut server_recv = lsp::framed::reader(server.stdout.take().unwrap());
+    if let Some(sub) = &claims.sub {
+        tracing::Span::current().record("subject", sub.as_str());
+    }
+    let Admitted {
+        connection: _connection,
+        instance,
+    } = match entry {
+        Entry::Admitted(admitted) => admitted,
+        Entry::Queued(ticket) => match wait_in_queue(&mut ws, ticket).await? {
+            Some(admitted) => admitted,
+            None => {
+                tracing::warn!("timed out waiting in the queue");
+                ws.send(warp::ws::Message::close_with(
+                    CLOSE_QUEUE_TIMEOUT,
+                    "timed out waiting in the queue",
+                ))
+                .await?;
+                return Ok(());
+            }
+        },
+    };
+
+    let mut session = if let Some(token) = query.as_ref().and_then(|q| q.session.as_ref()) {
+        tracing::info!("resuming session");
+        ctx.sessions
+            .reattach(token, &claims)
+            .await
+            .ok_or("no session to resume")?
+    } else {
+        let mut session = start_session(&ctx, &query, &claims).await?;
+        session.instance = instance;
+        session.claims = claims;
+        session.config = ctx.config.clone();
+        session.limiter = ctx
+            .config
+            .as_ref()
+            .and_then(|c| c.rate_limit.as_ref())
+            .and_then(MessageLimiter::new);
+        session
+    };
+    // Keep using the config the session started with even if reloaded since.
+    ctx.config = session.config.clone();
+    tracing::Span::current().record("session", session.id.as_str());
+    if let Some(sub) = &session.claims.sub {
+        tracing::Span::current().record("subject", sub.as_str());
+    }
+
+    // Let `/files` send messages to the server while connected.
+    let attached = ctx.sessions.attach(&session);
+    let result = run(ws, &ctx, &mut session).await;
+    drop(attached);
+    // Failing to send to the client means the client is gone.
+    let client_gone = match &result {
+        Ok(client_gone) => *client_gone,
+        Err(err) => err.is::<warp::Error>(),
+    };
+    match ctx.config.as_ref().and_then(|c| c.resume.as_ref()) {
+        Some(resume) if client_gone && session.is_resumable() => {
+            tracing::info!("detaching session");
+            ctx.sessions.detach(session, resume);
+        }
+        _ => session.close().await?,
+    }
+    result.map(|_| ())
+}
+
+/// Wait for the turn in the queue, sending the position to the client when it changes.
+/// Returns `None` if timed out.
+async fn wait_in_queue(
+    ws: &mut warp::ws::WebSocket,
+    ticket: Ticket,
+) -> Result<Option<Admitted>, Box<dyn std::error::Error + Send + Sync>> {
+    let timeout = tokio::time::sleep(ticket.timeout());
+    tokio::pin!(timeout);
+    let mut last_position = 0;
+    loop {
+        let changed = ticket.changed();
+        tokio::pin!(changed);
+        // Register before checking so that no changes are missed.
+        changed.as_mut().enable();
+        match ticket.try_admit() {
+            Ok(admitted) => return Ok(Some(admitted)),
+            Err(position) if position != last_position => {
+                last_position = position;
+                tracing::debug!("waiting in the queue at {}", position);
+                let notification = lsp::Message::Notification(lsp::Notification::Queue {
+                    params: QueueParams { position },
+                });
+                send_to_client(ws, &notification).await?;
+            }
+            Err(_) => {}
+        }
+
+        tokio::select! {
+            _ = changed => {}
+            _ = &mut timeout => return Ok(None),
+        }
+    }
+}
+
+/// Proxy messages until either side disconnects.
+/// Returns `true` if the client disconnected while the server is still running.
+async fn run(
+    ws: warp::ws::WebSocket,
+    ctx: &Context,
+    session: &mut Session,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
     let (mut client_send, client_recv) = ws.split();
     let client_recv = client_recv
         .filter_map(filter_map_warp_ws_message)
@@ -140,41 +470,106 @@ async fn connected(
     );
     let mut client_recv = stream::select(client_recv, ticks).boxed();
 
-    // let mut client_msg = client_recv.next();
-    // let mut server_msg = server_recv.next();
+    // Flush messages from the server while the client was away.
+    for text in session.take_buffered() {
+        forward_to_client(text, ctx, session, &mut client_send).await?;
+    }
+
+    // Shut down when the client hasn't sent any LSP messages for a while.
+    // Pongs don't count, so that abandoned tabs don't keep the server running.
+    let idle_timeout = ctx
+        .config
+        .as_ref()
+        .and_then(|c| c.idle_timeout_secs)
+        .map(Duration::from_secs);
+    let idle = tokio::time::sleep(idle_timeout.unwrap_or_default());
+    tokio::pin!(idle);
+    let shutdown = session.shutdown.requested();
+    tokio::pin!(shutdown);
+    let deadline = session.deadline();
+    let expire = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now));
+    tokio::pin!(expire);
+
+    // Set while waiting to restart the exited server.
+    let mut restarting = false;
+    let restart = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(restart);
+
     // Keeps track if `pong` was received since sending the last `ping`.
     let mut is_alive = true;
-
-    let mut database = None;
     loop {
         tokio::select! {
             from_client = client_recv.next() => {
+                if let (Some(timeout), Some(Ok(Message::Message(_) | Message::Batch(_) | Message::Invalid(_)))) =
+                    (idle_timeout, &from_client)
+                {
+                    idle.as_mut().reset(tokio::time::Instant::now() + timeout);
+                }
                 match from_client {
                     // Valid LSP message
-                    Some(Ok(Message::Message(mut msg))) => {
-                        if ctx.remap {
-                            lsp::ext::remap_relative_uri(&mut msg, &ctx.cwd)?;
-                            tracing::debug!("remapped relative URI from client");
+                    Some(Ok(Message::Message(msg))) => {
+                        let mut replies = Vec::new();
+                        let msg = process_client_message(msg, ctx, session, &mut replies).await?;
+                        for reply in &replies {
+                            send_to_client(&mut client_send, reply).await?;
                         }
-                        if ctx.sync {
-                            maybe_write_text_document(&msg).await?;
+                        match msg {
+                            Some(msg) if restarting => {
+                                reject_while_restarting(vec![msg], session, &mut client_send).await?;
+                            }
+                            Some(msg) => {
+                                let text = serde_json::to_string(&msg)?;
+                                tracing::debug!("-> {}", text);
+                                session.server.send.send(text).await?;
+                            }
+                            None => {}
                         }
+                    }
 
-                        database = lsp::ext::create_database_on_init(
-                            &mut msg,
-                            "sql",
-                            ctx.config.as_ref(),
-                        ).await?;
-                        let text = serde_json::to_string(&msg)?;
-                        tracing::debug!("-> {}", text);
-                        server_send.send(text).await?;
+                    // Batch of LSP messages
+                    Some(Ok(Message::Batch(batch))) => {
+                        let mut replies = Vec::new();
+                        let mut messages = Vec::with_capacity(batch.len());
+                        for msg in batch {
+                            if let Some(msg) = process_client_message(msg, ctx, session, &mut replies).await? {
+                                messages.push(msg);
+                            }
+                        }
+                        // Responses from the proxy are sent back with the ones from the server.
+                        let (responses, others): (Vec<_>, Vec<_>) = replies
+                            .into_iter()
+                            .partition(|reply| reply.response_id().is_some());
+                        let ids = messages.iter().filter_map(lsp::Message::request_id).collect();
+                        if let Some(responses) = session.batches.start(ids, responses) {
+                            send_to_client(&mut client_send, &responses).await?;
+                        }
+                        for reply in &others {
+                            send_to_client(&mut client_send, reply).await?;
+                        }
+                        if restarting {
+                            reject_while_restarting(messages, session, &mut client_send).await?;
+                        } else if session.server_config.batch {
+                            if !messages.is_empty() {
+                                let text = serde_json::to_string(&messages)?;
+                                tracing::debug!("-> {}", text);
+                                session.server.send.send(text).await?;
+                            }
+                        } else {
+                            for msg in &messages {
+                                let text = serde_json::to_string(msg)?;
+                                tracing::debug!("-> {}", text);
+                                session.server.send.send(text).await?;
+                            }
+                        }
                     }
 
                     // Invalid JSON body
                     Some(Ok(Message::Invalid(text))) => {
                         tracing::warn!("-> {}", text);
                         // Just forward it to the server as is.
-                        server_send.send(text).await?;
+                        if !restarting {
+                            session.server.send.send(text).await?;
+                        }
                     }
 
                     // Close message
@@ -188,7 +583,7 @@ async fn connected(
                         // Terminate if we haven't heard back from the previous ping.
                         if !is_alive {
                             tracing::warn!("terminating unhealthy connection");
-                            break;
+                            return Ok(true);
                         }
 
                         is_alive = false;
@@ -205,7 +600,7 @@ async fn connected(
                     // Connection closed
                     Some(Ok(Message::Done)) => {
                         tracing::info!("connection closed");
-                        break;
+                        return Ok(true);
                     }
 
                     // WebSocket Error
@@ -219,25 +614,11 @@ async fn connected(
                     }
                 }
             }
-            from_server = server_recv.next() => {
+            from_server = session.server.recv.next(), if !restarting => {
                 match from_server {
                     // Serialized LSP Message
                     Some(Ok(text)) => {
-                        if ctx.remap {
-                            if let Ok(mut msg) = lsp::Message::from_str(&text) {
-                                lsp::ext::remap_relative_uri(&mut msg, &ctx.cwd)?;
-                                tracing::debug!("remapped relative URI from server");
-                                let text = serde_json::to_string(&msg)?;
-                                tracing::debug!("<- {}", text);
-                                client_send.send(warp::ws::Message::text(text)).await?;
-                            } else {
-                                tracing::warn!("<- {}", text);
-                                client_send.send(warp::ws::Message::text(text)).await?;
-                            }
-                        } else {
-                            tracing::debug!("<- {}", text);
-                            client_send.send(warp::ws::Message::text(text)).await?;
-                        }
+                        forward_to_client(text, ctx, session, &mut client_send).await?;
                     }
 
                     // Codec Error
@@ -247,29 +628,498 @@ async fn connected(
 
                     // Server exited
                     None => {
-                        tracing::error!("server process exited unexpectedly");
-                        client_send.send(warp::ws::Message::close()).await?;
-                        break;
+                        // Show why it exited, especially when it failed to start.
+                        for line in session.server.stderr.remaining().await {
+                            send_to_client(&mut client_send, &stderr_line(line)).await?;
+                        }
+                        if let Some(message) = session.server.exceeded_limit().await {
+                            tracing::warn!("{}", message);
+                            send_to_client(&mut client_send, &limit_exceeded(message)).await?;
+                        }
+                        match prepare_restart(session, &mut client_send).await? {
+                            Some(backoff) => {
+                                restart.as_mut().reset(tokio::time::Instant::now() + backoff);
+                                restarting = true;
+                            }
+                            None => {
+                                tracing::error!("server process exited unexpectedly");
+                                client_send.send(warp::ws::Message::close()).await?;
+                                return Ok(false);
+                            }
+                        }
                     }
                 }
             }
+            _ = &mut restart, if restarting => {
+                restarting = false;
+                // The client may have asked to shut down while waiting.
+                if session.replay.as_ref().is_some_and(|r| r.is_exiting()) {
+                    client_send.send(warp::ws::Message::close()).await?;
+                    return Ok(false);
+                }
+                restart_server(session, &mut client_send).await?;
+            }
+            Some(line) = session.server.stderr.next() => {
+                send_to_client(&mut client_send, &stderr_line(line)).await?;
+            }
+            // Kept until the server is restarted.
+            Some(proxy_message) = session.proxy_messages.recv(), if !restarting => {
+                send_proxy_message(session, proxy_message).await?;
+            }
+            _ = &mut idle, if idle_timeout.is_some() => {
+                tracing::info!("shutting down idle session");
+                session.server.shutdown(SHUTDOWN_TIMEOUT).await;
+                client_send
+                    .send(warp::ws::Message::close_with(CLOSE_IDLE, "idle timeout"))
+                    .await?;
+                return Ok(false);
+            }
+            _ = &mut expire, if deadline.is_some() => {
+                tracing::info!("session reached the maximum duration");
+                session.server.shutdown(SHUTDOWN_TIMEOUT).await;
+                client_send
+                    .send(warp::ws::Message::close_with(CLOSE_EXPIRED, "session expired"))
+                    .await?;
+                return Ok(false);
+            }
+            _ = &mut shutdown => {
+                tracing::info!("shutting down session");
+                session.server.shutdown(SHUTDOWN_TIMEOUT).await;
+                client_send
+                    .send(warp::ws::Message::close_with(CLOSE_SHUTDOWN, "proxy shutting down"))
+                    .await?;
+                return Ok(false);
+            }
         }
     }
+}
 
-    if let Some(mut database) = database {
-        tracing::info!("drop database: {}", database.id());
-        database.cleanup().await?;
+/// Process a message from the client. Returns the message to send to the server,
+/// or `None` if it was handled by the proxy. Messages from the proxy to the client
+/// are added to `replies`.
+async fn process_client_message(
+    mut msg: lsp::Message,
+    ctx: &Context,
+    session: &mut Session,
+    replies: &mut Vec<lsp::Message>,
+) -> Result<Option<lsp::Message>, Box<dyn std::error::Error + Send + Sync>> {
+    if !session.limiter.as_mut().is_none_or(|l| l.allows(&msg)) {
+        match msg.request_id() {
+            Some(id) => {
+                tracing::debug!("rejecting request over the rate limit");
+                replies.push(rate_limited_error(id));
+            }
+            None => tracing::debug!("dropping notification over the rate limit"),
+        }
+        return Ok(None);
     }
+    match &msg {
+        lsp::Message::Request(lsp::Request::Initialize { id, .. }) => {
+            if let Some(driver) = lsp::ext::sqls_init_driver(&msg) {
+                if !session.claims.allows_sql_driver(driver) {
+                    tracing::warn!(
+                        subject = session.claims.subject(),
+                        "the token does not allow sql driver {:?}",
+                        driver
+                    );
+                    let message = format!("sql driver {:?} is not allowed", driver);
+                    replies.push(not_allowed_error(id.clone(), message));
+                    return Ok(None);
+                }
+            }
+            // The server is already initialized. Either pre-initialized,
+            // or the client is resuming the session.
+            if let Some(result) = &session.initialize_result {
+                replies.push(lsp::Message::Response(lsp::Response::Success {
+                    id: id.clone(),
+                    result: lsp::ResponseResult::Any(result.clone()),
+                }));
+                replies.push(session_params(session));
+                return Ok(None);
+            }
+            session.initialize_id = Some(id.clone());
+        }
 
+        lsp::Message::Notification(lsp::Notification::Initialized { .. }) => {
+            if session.initialized {
+                tracing::debug!("ignoring duplicate initialized");
+                return Ok(None);
+            }
+            session.initialized = true;
+        }
+
+        _ => {}
+    }
+
+    if ctx.remap {
+        lsp::ext::remap_relative_uri(&mut msg, &session.workspace.uri)?;
+        tracing::debug!("remapped relative URI from client");
+    }
+    let mut msg = session.open_documents.client_message(msg);
+    if ctx.sync && !session.claims.read_only {
+        maybe_write_text_document(&msg, &session.workspace).await?;
+    }
+
+    if let Some(database) =
+        lsp::ext::create_database_on_init(&mut msg, "sql", ctx.config.as_deref()).await?
+    {
+        session.database = Some(database);
+    }
+    if let Some(replay) = &mut session.replay {
+        replay.client_message(&msg);
+    }
+    Ok(Some(msg))
+}
+
+/// Send a message from the server to the client.
+async fn forward_to_client(
+    text: String,
+    ctx: &Context,
+    session: &mut Session,
+    client_send: &mut ClientSink,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Keep a copy to send when the client resumes in case this fails.
+    let raw = session.token.is_some().then(|| text.clone());
+    let mut issue_token = false;
+    let mut texts = Vec::new();
+    if !ctx.remap
+        && session.replay.is_none()
+        && session.initialize_id.is_none()
+        && session.proxy_requests.is_empty()
+        && session.batches.is_empty()
+    {
+        tracing::debug!("<- {}", text);
+        texts.push(text);
+    } else {
+        match lsp::Frame::from_str(&text) {
+            Ok(lsp::Frame::Single(msg)) => {
+                if let Some(msg) = process_server_message(msg, ctx, session, &mut issue_token)? {
+                    match session.batches.add(msg) {
+                        Batched::No(msg) => texts.push(serde_json::to_string(&msg)?),
+                        Batched::Held => {}
+                        Batched::Complete(responses) => {
+                            texts.push(serde_json::to_string(&responses)?);
+                        }
+                    }
+                }
+            }
+
+            // Batch from a server supporting them.
+            Ok(lsp::Frame::Batch(batch)) => {
+                let mut messages = Vec::with_capacity(batch.len());
+                for msg in batch {
+                    if let Some(msg) = process_server_message(msg, ctx, session, &mut issue_token)?
+                    {
+                        match session.batches.add(msg) {
+                            Batched::No(msg) => messages.push(msg),
+                            Batched::Held => {}
+                            Batched::Complete(responses) => {
+                                texts.push(serde_json::to_string(&responses)?);
+                            }
+                        }
+                    }
+                }
+                if !messages.is_empty() {
+                    texts.push(serde_json::to_string(&messages)?);
+                }
+            }
+
+            Err(_) => {
+                tracing::warn!("<- {}", text);
+                texts.push(text);
+            }
+        }
+    }
+
+    for text in texts {
+        if let Err(err) = client_send.send(warp::ws::Message::text(text)).await {
+            if let Some(raw) = raw {
+                session.buffer(raw);
+            }
+            return Err(err.into());
+        }
+    }
+    if issue_token {
+        send_session_params(client_send, session).await?;
+    }
+    Ok(())
+}
+
+/// Process a message from the server. Returns the message to send to the client,
+/// or `None` if it's not for the client. Sets `issue_token` when the server was initialized.
+fn process_server_message(
+    mut msg: lsp::Message,
+    ctx: &Context,
+    session: &mut Session,
+    issue_token: &mut bool,
+) -> Result<Option<lsp::Message>, Box<dyn std::error::Error + Send + Sync>> {
+    // Responses to the requests from the proxy are not for the client.
+    if let lsp::Message::Response(response) = &msg {
+        let id = msg.response_id();
+        if let Some(tx) = id.and_then(|id| session.proxy_requests.remove(id)) {
+            tracing::debug!("<- (proxy) {}", serde_json::to_string(&msg)?);
+            let _ = tx.send(response.clone());
+            return Ok(None);
+        }
+    }
+    if let Some(replay) = &mut session.replay {
+        if !replay.server_message(&msg) {
+            tracing::debug!("<- (replay) {}", serde_json::to_string(&msg)?);
+            return Ok(None);
+        }
+    }
+    if ctx.remap {
+        lsp::ext::remap_relative_uri(&mut msg, &session.workspace.uri)?;
+        tracing::debug!("remapped relative URI from server");
+    }
+    // Keep the result to answer `initialize` from the client resuming the session.
+    if let lsp::Message::Response(lsp::Response::Success { id, result }) = &msg {
+        if session.initialize_id.as_ref() == Some(id) {
+            session.initialize_id = None;
+            session.set_initialize_result(serde_json::to_value(result)?);
+            *issue_token = true;
+        }
+    }
+    tracing::debug!("<- {}", serde_json::to_string(&msg)?);
+    Ok(Some(msg))
+}
+
+/// Fail the requests the exited server will never respond to, if allowed to restart it by
+/// the restart policy. Returns how long to wait before restarting, or `None` if the server
+/// should not be restarted.
+async fn prepare_restart(
+    session: &mut Session,
+    client_send: &mut ClientSink,
+) -> Result<Option<Duration>, Box<dyn std::error::Error + Send + Sync>> {
+    let (restart, replay) = match (&session.server_config.restart, &mut session.replay) {
+        (Some(restart), Some(replay))
+            if !replay.is_exiting() && session.restarts < restart.max_restarts =>
+        {
+            (restart, replay)
+        }
+        _ => return Ok(None),
+    };
+
+    tracing::warn!("server process exited unexpectedly, restarting");
+    for id in replay.take_pending() {
+        match session.batches.add(server_exited_error(id)) {
+            Batched::No(error) => send_to_client(client_send, &error).await?,
+            Batched::Held => {}
+            Batched::Complete(responses) => send_to_client(client_send, &responses).await?,
+        }
+    }
+    // Dropping the senders tells the waiting requests from the proxy.
+    session.proxy_requests.clear();
+    Ok(Some(restart.backoff(session.restarts)))
+}
+
+/// Restart the server after `prepare_restart`, and bring it up to date with the session.
+async fn restart_server(
+    session: &mut Session,
+    client_send: &mut ClientSink,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (restart, replay) = match (&session.server_config.restart, &mut session.replay) {
+        (Some(restart), Some(replay)) => (restart, replay),
+        _ => return Ok(()),
+    };
+    session.restarts += 1;
+    session.server = Server::spawn(&session.launch)?;
+    session.server.set_session(&session.id);
+    let command = &session.launch.command;
+    tracing::info!(
+        "restarted {} ({}/{})",
+        command[0],
+        session.restarts,
+        restart.max_restarts
+    );
+    // Nothing else can be sent until the server responds to `initialize`.
+    let initialized = match replay.initialize_request() {
+        Some(request) => {
+            tracing::debug!("-> (replay) {}", serde_json::to_string(&request)?);
+            match tokio::time::timeout(INITIALIZE_TIMEOUT, session.server.initialize(&request))
+                .await
+            {
+                Ok(Ok(_)) => true,
+                Ok(Err(err)) => {
+                    tracing::warn!("failed to initialize the restarted server: {}", err);
+                    false
+                }
+                Err(_) => {
+                    tracing::warn!("timed out initializing the restarted server");
+                    false
+                }
+            }
+        }
+        None => true,
+    };
+    if initialized {
+        for msg in replay.messages() {
+            let text = serde_json::to_string(&msg)?;
+            tracing::debug!("-> (replay) {}", text);
+            session.server.send.send(text).await?;
+        }
+    }
+
+    let notification = lsp::Message::Notification(lsp::Notification::ServerRestarted {
+        params: ServerRestartedParams {
+            restarts: session.restarts,
+            max_restarts: restart.max_restarts,
+        },
+    });
+    send_to_client(client_send, &notification).await?;
+    Ok(())
+}
+
+/// Answer the requests in `messages` while the server is restarting. Notifications are
+/// dropped, the restarted server is brought up to date with the documents in `Replay`.
+async fn reject_while_restarting(
+    messages: Vec<lsp::Message>,
+    session: &mut Session,
+    client_send: &mut ClientSink,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for msg in messages {
+        let id = match msg.request_id() {
+            Some(id) => id,
+            None => {
+                tracing::debug!("dropping notification while the server is restarting");
+                continue;
+            }
+        };
+        let error = server_restarting_error(id);
+        if let Some(replay) = &mut session.replay {
+            replay.server_message(&error);
+        }
+        match session.batches.add(error) {
+            Batched::No(error) => send_to_client(client_send, &error).await?,
+            Batched::Held => {}
+            Batched::Complete(responses) => send_to_client(client_send, &responses).await?,
+        }
+    }
     Ok(())
 }
 
+/// Send a message from outside of the connection to the server.
+async fn send_proxy_message(
+    session: &mut Session,
+    proxy_message: ProxyMessage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let ProxyMessage { message, response } = proxy_message;
+    if let (Some(id), Some(response)) = (message.request_id(), response) {
+        session.proxy_requests.insert(id, response);
+    }
+    let text = serde_json::to_string(&message)?;
+    tracing::debug!("-> (proxy) {}", text);
+    session.server.send.send(text).await?;
+    Ok(())
+}
+
+/// Send `proxy/session` to tell the client about the session.
+async fn send_session_params(
+    client_send: &mut ClientSink,
+    session: &Session,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    send_to_client(client_send, &session_params(session)).await?;
+    Ok(())
+}
+
+// Notification to tell the client about the session.
+fn session_params(session: &Session) -> lsp::Message {
+    lsp::Message::Notification(lsp::Notification::Session {
+        params: session.params(),
+    })
+}
+
+/// Send a message or a batch created by the proxy to the client.
+async fn send_to_client<S, T>(
+    client_send: &mut S,
+    msg: &T,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: Sink<warp::ws::Message, Error = warp::Error> + Unpin,
+    T: serde::Serialize + ?Sized,
+{
+    let text = serde_json::to_string(msg)?;
+    tracing::debug!("<- (proxy) {}", text);
+    client_send.send(warp::ws::Message::text(text)).await?;
+    Ok(())
+}
+
+// Tell the user why the server exited.
+fn limit_exceeded(message: String) -> lsp::Message {
+    lsp::Message::Notification(lsp::Notification::ShowMessage {
+        params: lsp_types::ShowMessageParams {
+            typ: lsp_types::MessageType::ERROR,
+            message,
+        },
+    })
+}
+
+// Forward a line from stderr of the server.
+fn stderr_line(message: String) -> lsp::Message {
+    lsp::Message::Notification(lsp::Notification::LogMessage {
+        params: lsp_types::LogMessageParams {
+            typ: lsp_types::MessageType::LOG,
+            message,
+        },
+    })
+}
+
+// Error response for requests not allowed by the token.
+fn not_allowed_error(id: Id, message: String) -> lsp::Message {
+    lsp::Message::Response(lsp::Response::Failure {
+        id: Some(id),
+        error: lsp::error::Error {
+            code: lsp::error::ErrorCode::InvalidRequest,
+            message,
+            data: None,
+        },
+    })
+}
+
+// Error response for requests over the rate limit.
+fn rate_limited_error(id: Id) -> lsp::Message {
+    lsp::Message::Response(lsp::Response::Failure {
+        id: Some(id),
+        error: lsp::error::Error {
+            code: lsp::error::ErrorCode::ContentModified,
+            message: "rate limit exceeded".to_owned(),
+            data: None,
+        },
+    })
+}
+
+// Error response for requests in flight when the server exited.
+fn server_exited_error(id: Id) -> lsp::Message {
+    lsp::Message::Response(lsp::Response::Failure {
+        id: Some(id),
+        error: lsp::error::Error {
+            code: lsp::error::ErrorCode::InternalError,
+            message: "server exited unexpectedly".to_owned(),
+            data: None,
+        },
+    })
+}
+
+// Error response for requests received while the server is restarting.
+fn server_restarting_error(id: Id) -> lsp::Message {
+    lsp::Message::Response(lsp::Response::Failure {
+        id: Some(id),
+        error: lsp::error::Error {
+            code: lsp::error::ErrorCode::ContentModified,
+            message: "server is restarting".to_owned(),
+            data: None,
+        },
+    })
+}
+
 // Type to describe a message from the client conveniently.
 #[allow(clippy::large_enum_variant)]
 #[allow(clippy::enum_variant_names)]
 enum Message {
     // Valid LSP message
     Message(lsp::Message),
+    // Batch of valid LSP messages
+    Batch(Vec<lsp::Message>),
     // Invalid JSON
     Invalid(String),
     // Close message
@@ -293,9 +1143,13 @@ async fn filter_map_warp_ws_message(
                 Some(Ok(Message::Close))
             } else if msg.is_text() {
                 let text = msg.to_str().expect("text");
-                match lsp::Message::from_str(text) {
-                    Ok(msg) => Some(Ok(Message::Message(msg))),
-                    Err(_) => Some(Ok(Message::Invalid(text.to_owned()))),
+                match lsp::Frame::from_str(text) {
+                    Ok(lsp::Frame::Single(msg)) => Some(Ok(Message::Message(msg))),
+                    // An empty batch is invalid.
+                    Ok(lsp::Frame::Batch(batch)) if !batch.is_empty() => {
+                        Some(Ok(Message::Batch(batch)))
+                    }
+                    _ => Some(Ok(Message::Invalid(text.to_owned()))),
                 }
             } else if msg.is_pong() {
                 Some(Ok(Message::Pong))
@@ -0,0 +1,237 @@
+//! Token buckets limiting the rate of LSP messages and HTTP requests.
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use warp::{Filter, Rejection};
+
+use crate::{
+    config::{RateLimit, RateLimitConfig},
+    listen::Peer,
+    lsp,
+};
+
+/// Number of IP addresses to track before forgetting the ones not limited anymore.
+const MAX_TRACKED_IPS: usize = 1024;
+
+/// Allows `burst` at once, refilled at `per_sec`.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        let burst = limit.burst.map_or(limit.per_sec.ceil(), f64::from).max(1.0);
+        Self {
+            per_sec: limit.per_sec.max(0.0),
+            burst,
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.burst);
+        self.last = now;
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns `true` if not used long enough to be full again.
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.burst
+    }
+}
+
+/// Limits of LSP messages from the client of a session.
+#[derive(Debug)]
+pub struct MessageLimiter {
+    methods: HashMap<String, TokenBucket>,
+    /// Shared by the requests without their own limit.
+    other_requests: Option<TokenBucket>,
+    /// Only warn on the first message over the limit.
+    warned: bool,
+}
+
+impl MessageLimiter {
+    /// Returns `None` if no methods are limited.
+    pub fn new(config: &RateLimitConfig) -> Option<Self> {
+        if config.methods.is_empty() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut methods = config
+            .methods
+            .iter()
+            .map(|(method, limit)| (method.clone(), TokenBucket::new(limit, now)))
+            .collect::<HashMap<_, _>>();
+        let other_requests = methods.remove("*");
+        Some(Self {
+            methods,
+            other_requests,
+            warned: false,
+        })
+    }
+
+    /// Returns `false` if `msg` is over the limit. Responses are never limited.
+    pub fn allows(&mut self, msg: &lsp::Message) -> bool {
+        let method = match msg.method() {
+            Some(method) => method,
+            None => return true,
+        };
+        let bucket = match self.methods.get_mut(method) {
+            Some(bucket) => bucket,
+            None if msg.request_id().is_some() => match &mut self.other_requests {
+                Some(bucket) => bucket,
+                None => return true,
+            },
+            None => return true,
+        };
+        if bucket.try_take(Instant::now()) {
+            return true;
+        }
+        if !self.warned {
+            self.warned = true;
+            tracing::warn!("client exceeded the rate limit of {}", method);
+        }
+        false
+    }
+}
+
+/// Limits of requests from each IP address.
+#[derive(Debug)]
+pub struct IpLimiter {
+    limit: RateLimit,
+    /// Connections without a known address share `None`.
+    buckets: Mutex<HashMap<Option<IpAddr>, TokenBucket>>,
+}
+
+impl IpLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn allows(&self, ip: Option<IpAddr>) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_IPS && !buckets.contains_key(&ip) {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(&self.limit, now))
+            .try_take(now)
+    }
+}
+
+/// Rejection for requests over the limit.
+#[derive(Debug)]
+pub struct TooManyRequests;
+
+impl warp::reject::Reject for TooManyRequests {}
+
+/// Reject requests over the limit of the IP address. Not limited if `limiter` is `None`.
+/// Requests from unix sockets are not limited.
+pub fn by_ip(
+    limiter: Option<Arc<IpLimiter>>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<Peer>())
+        .and_then(move |remote: Option<SocketAddr>, peer: Option<Peer>| {
+            let limiter = limiter.clone();
+            async move {
+                let addr = match peer {
+                    Some(Peer::Tcp(addr)) => Some(addr),
+                    Some(Peer::Unix) => return Ok(()),
+                    None => remote,
+                };
+                match limiter {
+                    Some(limiter) if !limiter.allows(addr.map(|a| a.ip())) => {
+                        tracing::warn!("too many requests from {:?}", addr);
+                        Err(warp::reject::custom(TooManyRequests))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(
+            &RateLimit {
+                per_sec: 2.0,
+                burst: Some(3),
+            },
+            now,
+        );
+        assert!((0..3).all(|_| bucket.try_take(now)));
+        assert!(!bucket.try_take(now));
+        assert!(bucket.try_take(now + Duration::from_millis(500)));
+        assert!(!bucket.try_take(now + Duration::from_millis(500)));
+        assert!(!bucket.is_full(now + Duration::from_secs(1)));
+        assert!(bucket.is_full(now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_message_limiter() {
+        let limit = |per_sec, burst| RateLimit {
+            per_sec,
+            burst: Some(burst),
+        };
+        let mut limiter = MessageLimiter::new(&RateLimitConfig {
+            methods: HashMap::from([
+                ("textDocument/completion".to_owned(), limit(0.001, 2)),
+                ("*".to_owned(), limit(0.001, 1)),
+            ]),
+            files: None,
+        })
+        .unwrap();
+        let message = |v: serde_json::Value| serde_json::from_value::<lsp::Message>(v).unwrap();
+        let completion = message(json!({
+            "jsonrpc":"2.0","method":"textDocument/completion","id":1,
+            "params":{"textDocument":{"uri":"file:///a.rs"},"position":{"line":0,"character":0}}
+        }));
+        let other = message(json!({"jsonrpc":"2.0","method":"custom/request","id":2,"params":{}}));
+        let notification =
+            message(json!({"jsonrpc":"2.0","method":"custom/notification","params":{}}));
+
+        assert!(limiter.allows(&completion));
+        assert!(limiter.allows(&completion));
+        assert!(!limiter.allows(&completion));
+        assert!(limiter.allows(&other));
+        assert!(!limiter.allows(&other));
+        // Notifications are only limited by their own method.
+        assert!((0..10).all(|_| limiter.allows(&notification)));
+    }
+}
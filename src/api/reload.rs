@@ -0,0 +1,102 @@
+//! Reload the config on SIGHUP and when the file changes.
+use std::{sync::Arc, time::Duration, time::SystemTime};
+
+use tokio::sync::watch;
+
+use crate::config::{read_config_from_file, Config};
+
+/// Interval of checking the config file for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Watch the config file at `path` loaded as `config`.
+/// Returns the receiver of the latest valid config.
+pub fn watch(path: String, config: Arc<Config>) -> watch::Receiver<Arc<Config>> {
+    // Settings only read on start keep the values from this.
+    let started = config.clone();
+    let (tx, rx) = watch::channel(config);
+    tokio::spawn(async move {
+        let mut hangup = Hangup::new();
+        let mut ticks = tokio::time::interval(POLL_INTERVAL);
+        let mut modified = modified_time(&path).await;
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    tracing::info!("reloading config on SIGHUP");
+                }
+                _ = ticks.tick() => {
+                    let current = modified_time(&path).await;
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                    tracing::info!("reloading config on change");
+                }
+                _ = tx.closed() => break,
+            }
+            reload(&path, &tx, &started).await;
+        }
+    });
+    rx
+}
+
+/// Swap in the config from `path` if it's valid, keeping the current one otherwise.
+async fn reload(path: &str, tx: &watch::Sender<Arc<Config>>, started: &Config) {
+    let read = {
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || read_config_from_file(&path))
+    };
+    let config = match read.await {
+        Ok(Ok(config)) => config,
+        Ok(Err(err)) => {
+            tracing::warn!(
+                "failed to reload config {}, keeping the current: {}",
+                path,
+                err
+            );
+            return;
+        }
+        Err(err) => {
+            tracing::error!("failed to reload config {}: {}", path, err);
+            return;
+        }
+    };
+    let restart = started.changes_requiring_restart(&config);
+    if !restart.is_empty() {
+        tracing::warn!(
+            "changes to {} take effect after restart",
+            restart.join(", ")
+        );
+    }
+    tx.send_replace(Arc::new(config));
+    tracing::info!("reloaded config {}", path);
+}
+
+async fn modified_time(path: &str) -> Option<SystemTime> {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Receives SIGHUP. Never resolves on platforms without it.
+struct Hangup {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        Self {
+            #[cfg(unix)]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .expect("signal handler"),
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        self.signal.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    }
+}
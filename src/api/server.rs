@@ -0,0 +1,284 @@
+use std::{collections::HashMap, path::PathBuf, process::Stdio, str::FromStr, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::{
+    config::{ForwardStderrConfig, LimitsConfig, ServerConfig},
+    lsp::{
+        self,
+        framed::{self, LspFrameCodec},
+        types::Id,
+    },
+};
+
+use super::{
+    limits::{self, Cgroup},
+    stderr::{self, Stderr},
+};
+
+/// ID used for the `shutdown` request sent by the proxy.
+const SHUTDOWN_ID: &str = "lsp-ws-proxy/shutdown";
+
+/// How long to wait for the server to respond to `initialize`.
+pub const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long to wait for the server to exit after `shutdown` and `exit`.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Values for placeholders in `ServerConfig`.
+#[derive(Debug)]
+pub struct Placeholders<'a> {
+    /// `${session_id}`
+    pub session_id: &'a str,
+    /// `${workspace}`
+    pub workspace: &'a str,
+    /// `${query.<name>}`
+    pub query: &'a HashMap<String, String>,
+}
+
+impl Placeholders<'_> {
+    fn get(&self, name: &str) -> Result<Option<&str>, String> {
+        match name {
+            "session_id" => Ok(Some(self.session_id)),
+            "workspace" => Ok(Some(self.workspace)),
+            _ => match name.strip_prefix("query.") {
+                Some(key) => self
+                    .query
+                    .get(key)
+                    .map(|v| Some(v.as_str()))
+                    .ok_or_else(|| format!("missing query parameter `{}`", key)),
+                // Leave anything else as is.
+                None => Ok(None),
+            },
+        }
+    }
+
+    fn expand(&self, value: &str) -> Result<String, String> {
+        shellexpand::env_with_context(value, |name| self.get(name))
+            .map(|v| v.into_owned())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// How to start a server, with placeholders expanded.
+#[derive(Debug, Clone)]
+pub struct Launch {
+    /// Name of the server used in logs.
+    pub name: String,
+    pub command: Vec<String>,
+    env: Vec<(String, String)>,
+    cwd: PathBuf,
+    clear_env: bool,
+    limits: Option<LimitsConfig>,
+    forward_stderr: Option<ForwardStderrConfig>,
+}
+
+impl Launch {
+    pub fn new(
+        name: &str,
+        config: &ServerConfig,
+        placeholders: &Placeholders,
+    ) -> Result<Self, String> {
+        let command = config
+            .command
+            .iter()
+            .map(|arg| placeholders.expand(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let env = config
+            .env
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), placeholders.expand(v)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let cwd = match &config.cwd {
+            Some(cwd) => PathBuf::from(placeholders.expand(cwd)?),
+            None => PathBuf::from(placeholders.workspace),
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            command,
+            env,
+            cwd,
+            clear_env: config.clear_env,
+            limits: config.limits.clone(),
+            forward_stderr: config.forward_stderr.clone(),
+        })
+    }
+}
+
+/// A running Language Server with framed stdio.
+#[derive(Debug)]
+pub struct Server {
+    // Kept so that the process is killed when dropped.
+    child: Child,
+    pub send: FramedWrite<ChildStdin, LspFrameCodec>,
+    pub recv: FramedRead<ChildStdout, LspFrameCodec>,
+    /// Lines from stderr to forward to the client.
+    pub stderr: Stderr,
+    /// Span stderr is logged in.
+    span: tracing::Span,
+    limits: Option<LimitsConfig>,
+    // Dropped after `child` so that the process is killed first.
+    cgroup: Option<Cgroup>,
+}
+
+impl Server {
+    /// Spawn the server with piped stdio. stderr is logged and forwarded if configured.
+    pub fn spawn(launch: &Launch) -> Result<Self, std::io::Error> {
+        let mut command = Command::new(&launch.command[0]);
+        command.args(&launch.command[1..]);
+        if launch.clear_env {
+            command.env_clear();
+        }
+        command.envs(launch.env.iter().map(|(k, v)| (k, v)));
+        command.current_dir(&launch.cwd);
+        let cgroup = match &launch.limits {
+            Some(limits) => limits::apply(&mut command, limits)?,
+            None => None,
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let send = framed::writer(child.stdin.take().unwrap());
+        let recv = framed::reader(child.stdout.take().unwrap());
+        let span = tracing::info_span!(
+            "stderr",
+            server = %launch.name,
+            session = tracing::field::Empty
+        );
+        let stderr = stderr::capture(
+            child.stderr.take().unwrap(),
+            span.clone(),
+            launch.forward_stderr.as_ref(),
+        );
+        Ok(Self {
+            child,
+            send,
+            recv,
+            stderr,
+            span,
+            limits: launch.limits.clone(),
+            cgroup,
+        })
+    }
+
+    /// Record the session using this server in the logs.
+    pub fn set_session(&self, id: &str) {
+        self.span.record("session", id);
+    }
+
+    /// Send `initialize` and wait for the result. Any other messages are discarded.
+    pub async fn initialize(
+        &mut self,
+        request: &lsp::Request,
+    ) -> Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>> {
+        let text = serde_json::to_string(&lsp::Message::Request(request.clone()))?;
+        self.send.send(text).await?;
+        while let Some(text) = self.recv.next().await {
+            let text = text?;
+            match lsp::Message::from_str(&text) {
+                Ok(lsp::Message::Response(lsp::Response::Success { id, result }))
+                    if &id == request.id() =>
+                {
+                    return Ok(serde_json::to_value(result)?);
+                }
+                Ok(lsp::Message::Response(lsp::Response::Failure { id, error }))
+                    if id.as_ref() == Some(request.id()) =>
+                {
+                    return Err(error.into());
+                }
+                _ => tracing::debug!("discarding message while initializing: {}", text),
+            }
+        }
+        Err("server exited while initializing".into())
+    }
+
+    /// Send `shutdown` and `exit`, and wait for the process to exit.
+    /// The process is killed if it doesn't exit within `timeout`.
+    pub async fn shutdown(&mut self, timeout: Duration) {
+        match tokio::time::timeout(timeout, self.shutdown_and_wait()).await {
+            Ok(Ok(status)) => tracing::debug!("server exited ({})", status),
+            Ok(Err(err)) => {
+                tracing::warn!("failed to shut down the server: {}", err);
+                let _ = self.child.kill().await;
+            }
+            Err(_) => {
+                tracing::warn!("server did not exit in {:?}, killing", timeout);
+                let _ = self.child.kill().await;
+            }
+        }
+    }
+
+    /// Send `shutdown` and `exit`, and wait for the process to exit without a timeout.
+    pub async fn shutdown_and_wait(
+        &mut self,
+    ) -> Result<std::process::ExitStatus, Box<dyn std::error::Error + Send + Sync>> {
+        let id = Id::String(SHUTDOWN_ID.to_owned());
+        let shutdown = lsp::Message::Request(lsp::Request::Shutdown {
+            id: id.clone(),
+            params: (),
+        });
+        self.send.send(serde_json::to_string(&shutdown)?).await?;
+        // Discard anything else until the response.
+        while let Some(text) = self.recv.next().await {
+            if let Ok(msg) = lsp::Message::from_str(&text?) {
+                if msg.response_id() == Some(&id) {
+                    break;
+                }
+            }
+        }
+        let exit = lsp::Message::Notification(lsp::Notification::Exit { params: () });
+        // The server may have exited already.
+        let _ = self.send.send(serde_json::to_string(&exit)?).await;
+        Ok(self.child.wait().await?)
+    }
+
+    /// After the server exited, describe the resource limit it exceeded if any.
+    pub async fn exceeded_limit(&mut self) -> Option<String> {
+        let limits = self.limits.as_ref()?;
+        // The output is closed, but the process may not have exited yet.
+        let status = tokio::time::timeout(Duration::from_secs(1), self.child.wait())
+            .await
+            .ok()?
+            .ok()?;
+        limits::exceeded(status, limits, self.cgroup.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_launch_placeholders() {
+        let config: ServerConfig = serde_json::from_value(serde_json::json!({
+            "command": ["jdtls", "-data", "/tmp/jdtls/${session_id}"],
+            "env": {"JAVA_HOME": "/opt/${query.jdk}", "OTHER": "$HOME"},
+            "cwd": "${workspace}",
+        }))
+        .unwrap();
+        let query = [("jdk".to_owned(), "jdk-21".to_owned())].into();
+        let placeholders = Placeholders {
+            session_id: "abc",
+            workspace: "/workspace",
+            query: &query,
+        };
+        let launch = Launch::new("jdtls", &config, &placeholders).unwrap();
+        assert_eq!(launch.command, vec!["jdtls", "-data", "/tmp/jdtls/abc"]);
+        let env: HashMap<_, _> = launch.env.into_iter().collect();
+        assert_eq!(env["JAVA_HOME"], "/opt/jdk-21");
+        assert_eq!(env["OTHER"], "$HOME");
+        assert_eq!(launch.cwd, PathBuf::from("/workspace"));
+
+        let empty = HashMap::new();
+        let placeholders = Placeholders {
+            query: &empty,
+            ..placeholders
+        };
+        assert!(Launch::new("jdtls", &config, &placeholders).is_err());
+    }
+}
@@ -0,0 +1,771 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use futures_util::StreamExt;
+use tokio::sync::{mpsc, oneshot};
+use url::Url;
+
+use crate::{
+    config::{Config, ResumeConfig, ServerConfig},
+    lsp::{
+        self,
+        ext::{proxy::SessionParams, SqlsDatabase},
+        types::Id,
+        Notification, Request,
+    },
+};
+
+use super::{
+    admission::Permit,
+    auth::Claims,
+    rate_limit::MessageLimiter,
+    server::{Launch, Server, SHUTDOWN_TIMEOUT},
+    shutdown,
+    workspace::Workspace,
+};
+
+/// Server side of a session. Outlives the WebSocket connection while detached.
+pub struct Session {
+    /// Unique ID of the session. Unlike `token`, this can't be used to resume the session.
+    pub id: String,
+    /// Token to resume the session. Only issued when resumption is enabled.
+    pub token: Option<String>,
+    pub server_config: ServerConfig,
+    /// Used to restart the server.
+    pub launch: Launch,
+    pub server: Server,
+    pub workspace: Workspace,
+    /// Counts the server toward `max_instances` while the session is alive.
+    pub instance: Option<Permit>,
+    /// Claims of the token the session was started with.
+    pub claims: Claims,
+    /// Config when the session started.
+    pub config: Option<Arc<Config>>,
+    /// Limits the rate of messages from the client if configured.
+    pub limiter: Option<MessageLimiter>,
+    started: Instant,
+    /// Only present when the server can be restarted.
+    pub replay: Option<Replay>,
+    pub restarts: u32,
+    pub database: Option<SqlsDatabase>,
+    /// `InitializeResult` to answer `initialize` from the client with
+    /// when the server is already initialized.
+    pub initialize_result: Option<serde_json::Value>,
+    /// ID of the `initialize` request from the client waiting for the result.
+    pub initialize_id: Option<Id>,
+    /// Set after `initialized` was sent to the server.
+    pub initialized: bool,
+    /// Messages from the server while detached.
+    buffered: Vec<String>,
+    /// Documents opened in the server.
+    pub open_documents: OpenDocuments,
+    /// Keeps the proxy running until the session is closed.
+    pub shutdown: shutdown::Guard,
+    /// Lets others send messages to the server while attached.
+    handle: SessionHandle,
+    /// Messages sent with `handle` to send to the server.
+    pub proxy_messages: mpsc::Receiver<ProxyMessage>,
+    /// Requests sent with `handle` waiting for the server to respond.
+    pub proxy_requests: HashMap<Id, oneshot::Sender<lsp::Response>>,
+    /// Batches from the client waiting for the responses.
+    pub batches: Batches,
+}
+
+impl Session {
+    pub fn new(
+        id: String,
+        server_config: ServerConfig,
+        launch: Launch,
+        server: Server,
+        workspace: Workspace,
+        token: Option<String>,
+        shutdown: shutdown::Guard,
+    ) -> Self {
+        let replay = server_config.restart.as_ref().map(|_| Replay::default());
+        server.set_session(&id);
+        let (tx, proxy_messages) = mpsc::channel(16);
+        Self {
+            id,
+            token,
+            server_config,
+            launch,
+            server,
+            workspace,
+            instance: None,
+            claims: Claims::default(),
+            config: None,
+            limiter: None,
+            started: Instant::now(),
+            replay,
+            restarts: 0,
+            database: None,
+            initialize_result: None,
+            initialize_id: None,
+            initialized: false,
+            buffered: Vec::new(),
+            open_documents: OpenDocuments::default(),
+            shutdown,
+            handle: SessionHandle {
+                send: tx,
+                capabilities: Default::default(),
+            },
+            proxy_messages,
+            proxy_requests: HashMap::new(),
+            batches: Batches::default(),
+        }
+    }
+
+    /// Keep the result of `initialize` from the server.
+    pub fn set_initialize_result(&mut self, result: serde_json::Value) {
+        *self.handle.capabilities.lock().unwrap() = result.get("capabilities").cloned();
+        self.initialize_result = Some(result);
+    }
+
+    /// When the session ends if the duration is limited by the claims.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.claims
+            .max_session_secs
+            .map(|secs| self.started + Duration::from_secs(secs))
+    }
+
+    /// Returns `true` if the client has the token and the session can be resumed.
+    pub fn is_resumable(&self) -> bool {
+        self.token.is_some() && self.initialized
+    }
+
+    /// Params of `proxy/session` to tell the client about the session.
+    pub fn params(&self) -> SessionParams {
+        SessionParams {
+            id: self.id.clone(),
+            token: self.token.clone(),
+        }
+    }
+
+    /// Keep a message from the server to send when the client resumes.
+    pub fn buffer(&mut self, text: String) {
+        self.buffered.push(text);
+    }
+
+    /// Take messages from the server buffered while detached.
+    pub fn take_buffered(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.buffered)
+    }
+
+    /// Clean up any resources created for this session and stop the server.
+    pub async fn close(mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Stop the server before removing the workspace it's using.
+        drop(self.server);
+        let workspace = self.workspace.cleanup().await;
+        if let Some(mut database) = self.database.take() {
+            tracing::info!("drop database: {}", database.id());
+            database.cleanup().await?;
+        }
+        Ok(workspace?)
+    }
+}
+
+/// Message to the server of a session from outside of the connection.
+#[derive(Debug)]
+pub struct ProxyMessage {
+    pub message: lsp::Message,
+    /// Receives the response if `message` is a request.
+    pub response: Option<oneshot::Sender<lsp::Response>>,
+}
+
+/// Sends messages to the server of an attached session. Used by `/files`.
+#[derive(Debug, Clone)]
+pub struct SessionHandle {
+    send: mpsc::Sender<ProxyMessage>,
+    /// `ServerCapabilities` once the server is initialized.
+    capabilities: Arc<Mutex<Option<serde_json::Value>>>,
+}
+
+impl SessionHandle {
+    /// `workspace.fileOperations` the server is interested in.
+    pub fn file_operations(&self) -> Option<lsp_types::WorkspaceFileOperationsServerCapabilities> {
+        let capabilities = self.capabilities.lock().unwrap();
+        let file_operations = capabilities
+            .as_ref()?
+            .get("workspace")?
+            .get("fileOperations")?;
+        serde_json::from_value(file_operations.clone()).ok()
+    }
+
+    /// Send `notification` to the server.
+    /// Returns `false` if the connection is gone.
+    pub async fn notify(&self, notification: Notification) -> bool {
+        let message = ProxyMessage {
+            message: lsp::Message::Notification(notification),
+            response: None,
+        };
+        self.send.send(message).await.is_ok()
+    }
+
+    /// Send `request` to the server and wait for the response.
+    /// Returns `None` if the connection or the server is gone before responding.
+    pub async fn request(&self, request: Request) -> Option<lsp::Response> {
+        let (tx, rx) = oneshot::channel();
+        let message = ProxyMessage {
+            message: lsp::Message::Request(request),
+            response: Some(tx),
+        };
+        self.send.send(message).await.ok()?;
+        rx.await.ok()
+    }
+}
+
+/// Sessions with a connection keyed by the ID, and
+/// detached sessions waiting for the client to reconnect keyed by the token.
+#[derive(Default)]
+pub struct Sessions {
+    /// Handles with the claims of the token each session was started with.
+    attached: Mutex<HashMap<String, (Claims, SessionHandle)>>,
+    detached: Mutex<HashMap<String, Detached>>,
+}
+
+/// Handle of a detached session.
+struct Detached {
+    /// Claims of the token the session was started with.
+    claims: Claims,
+    /// Takes the session from the task keeping it.
+    reattach: oneshot::Sender<oneshot::Sender<Session>>,
+}
+
+// Manually implemented to avoid showing the tokens.
+impl std::fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sessions")
+            .field("attached", &self.attached.lock().unwrap().len())
+            .field("detached", &self.detached.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Keeps the session reachable with `Sessions::handle` until dropped.
+pub struct Attached {
+    sessions: Arc<Sessions>,
+    id: String,
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        self.sessions.attached.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Sessions {
+    /// Make `session` reachable while connected.
+    pub fn attach(self: &Arc<Self>, session: &Session) -> Attached {
+        self.attached.lock().unwrap().insert(
+            session.id.clone(),
+            (session.claims.clone(), session.handle.clone()),
+        );
+        Attached {
+            sessions: self.clone(),
+            id: session.id.clone(),
+        }
+    }
+
+    /// Handle of the connected session `id` if `claims` allow accessing it.
+    pub fn handle(&self, id: &str, claims: &Claims) -> Option<SessionHandle> {
+        let attached = self.attached.lock().unwrap();
+        let (started, handle) = attached.get(id)?;
+        if !claims.can_access(started) {
+            tracing::warn!(
+                subject = claims.subject(),
+                "the token does not allow accessing the session"
+            );
+            return None;
+        }
+        Some(handle.clone())
+    }
+
+    /// Keep `session` running in the background until it's resumed or the grace period ends.
+    pub fn detach(self: &Arc<Self>, session: Session, config: &ResumeConfig) {
+        let token = session.token.clone().expect("resumable session has token");
+        let (tx, rx) = oneshot::channel();
+        self.detached.lock().unwrap().insert(
+            token.clone(),
+            Detached {
+                claims: session.claims.clone(),
+                reattach: tx,
+            },
+        );
+        tokio::spawn(run_detached(
+            self.clone(),
+            token,
+            session,
+            rx,
+            Duration::from_secs(config.grace_period_secs),
+            config.buffer_size,
+        ));
+    }
+
+    /// Take the detached session with `token` if `claims` allow resuming it.
+    pub async fn reattach(&self, token: &str, claims: &Claims) -> Option<Session> {
+        let handle = {
+            let mut detached = self.detached.lock().unwrap();
+            if !claims.can_access(&detached.get(token)?.claims) {
+                tracing::warn!(
+                    subject = claims.subject(),
+                    "the token does not allow resuming the session"
+                );
+                return None;
+            }
+            detached.remove(token)?.reattach
+        };
+        let (tx, rx) = oneshot::channel();
+        handle.send(tx).ok()?;
+        rx.await.ok()
+    }
+}
+
+async fn run_detached(
+    sessions: Arc<Sessions>,
+    token: String,
+    mut session: Session,
+    mut reattach: oneshot::Receiver<oneshot::Sender<Session>>,
+    grace_period: Duration,
+    buffer_size: usize,
+) {
+    let expire = tokio::time::sleep(grace_period);
+    tokio::pin!(expire);
+    let shutdown = session.shutdown.requested();
+    tokio::pin!(shutdown);
+    let deadline = session.deadline();
+    let expire_session = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now));
+    tokio::pin!(expire_session);
+    loop {
+        tokio::select! {
+            handle = &mut reattach => {
+                if let Ok(handle) = handle {
+                    tracing::info!("reattaching session");
+                    if let Err(session) = handle.send(session) {
+                        // The new connection is already gone.
+                        close_detached(session).await;
+                    }
+                }
+                return;
+            }
+
+            // Already logged. Keep the lines to forward from piling up to be sent on reattach.
+            Some(line) = session.server.stderr.next() => {
+                tracing::debug!("dropping stderr line while detached: {}", line);
+            }
+
+            from_server = session.server.recv.next() => match from_server {
+                Some(Ok(text)) => {
+                    if session.buffered.len() >= buffer_size {
+                        // The client can't recover from lost messages, so give up.
+                        tracing::warn!("too many messages while detached, terminating session");
+                        break;
+                    }
+                    tracing::debug!("<- (buffered) {}", text);
+                    session.buffer(text);
+                }
+                Some(Err(err)) => tracing::error!("{}", err),
+                None => {
+                    if let Some(message) = session.server.exceeded_limit().await {
+                        tracing::warn!("{}", message);
+                    }
+                    tracing::warn!("server process exited while detached");
+                    break;
+                }
+            },
+
+            _ = &mut expire => {
+                tracing::info!("detached session expired");
+                break;
+            }
+
+            _ = &mut shutdown => {
+                tracing::info!("shutting down detached session");
+                session.server.shutdown(SHUTDOWN_TIMEOUT).await;
+                break;
+            }
+
+            _ = &mut expire_session, if deadline.is_some() => {
+                tracing::info!("detached session reached the maximum duration");
+                session.server.shutdown(SHUTDOWN_TIMEOUT).await;
+                break;
+            }
+        }
+    }
+
+    sessions.detached.lock().unwrap().remove(&token);
+    close_detached(session).await;
+}
+
+async fn close_detached(session: Session) {
+    if let Err(err) = session.close().await {
+        tracing::error!("failed to close detached session: {}", err);
+    }
+}
+
+/// Documents opened in the server, so that a client resuming the session doesn't open them again.
+#[derive(Debug, Default)]
+pub struct OpenDocuments(HashSet<Url>);
+
+impl OpenDocuments {
+    /// Record a message sent from the client to the server. `didOpen` of a document already
+    /// open is replaced with `didChange` of the full text.
+    pub fn client_message(&mut self, msg: lsp::Message) -> lsp::Message {
+        match msg {
+            lsp::Message::Notification(Notification::DidOpen { params }) => {
+                let doc = params.text_document;
+                if self.0.insert(doc.uri.clone()) {
+                    return lsp::Message::Notification(Notification::DidOpen {
+                        params: lsp_types::DidOpenTextDocumentParams { text_document: doc },
+                    });
+                }
+                tracing::debug!("replacing didOpen of an open document with didChange");
+                lsp::Message::Notification(Notification::DidChange {
+                    params: lsp_types::DidChangeTextDocumentParams {
+                        text_document: lsp_types::VersionedTextDocumentIdentifier::new(
+                            doc.uri,
+                            doc.version,
+                        ),
+                        content_changes: vec![lsp_types::TextDocumentContentChangeEvent {
+                            range: None,
+                            range_length: None,
+                            text: doc.text,
+                        }],
+                    },
+                })
+            }
+
+            lsp::Message::Notification(Notification::DidClose { params }) => {
+                self.0.remove(&params.text_document.uri);
+                lsp::Message::Notification(Notification::DidClose { params })
+            }
+
+            msg => msg,
+        }
+    }
+}
+
+/// ID used for the `initialize` request replayed to a restarted server.
+/// The response to it is not forwarded to the client.
+pub const REPLAY_INITIALIZE_ID: &str = "lsp-ws-proxy/replay/initialize";
+
+/// State of a session necessary to bring a restarted server up to date.
+#[derive(Debug, Default)]
+pub struct Replay {
+    /// `initialize` request as sent to the server.
+    initialize: Option<Request>,
+    /// `initialized` notification as sent to the server.
+    initialized: Option<Notification>,
+    /// Open documents with the latest changes applied.
+    documents: HashMap<Url, lsp_types::TextDocumentItem>,
+    /// IDs of requests from the client waiting for the server to respond.
+    pending: HashSet<Id>,
+    /// Set when the client asked the server to shut down or exit.
+    exiting: bool,
+}
+
+impl Replay {
+    /// Record a message sent from the client to the server.
+    pub fn client_message(&mut self, msg: &lsp::Message) {
+        if let Some(id) = msg.request_id() {
+            self.pending.insert(id);
+        }
+
+        match msg {
+            lsp::Message::Request(request @ Request::Initialize { .. }) => {
+                self.initialize = Some(request.clone());
+            }
+
+            lsp::Message::Request(Request::Shutdown { .. })
+            | lsp::Message::Notification(Notification::Exit { .. }) => {
+                self.exiting = true;
+            }
+
+            lsp::Message::Notification(notification) => match notification {
+                Notification::Initialized { .. } => {
+                    self.initialized = Some(notification.clone());
+                }
+
+                Notification::DidOpen { params: p } => {
+                    self.documents
+                        .insert(p.text_document.uri.clone(), p.text_document.clone());
+                }
+
+                Notification::DidChange { params: p } => {
+                    if let Some(doc) = self.documents.get_mut(&p.text_document.uri) {
+                        doc.version = p.text_document.version;
+                        for change in &p.content_changes {
+                            apply_change(&mut doc.text, change);
+                        }
+                    }
+                }
+
+                Notification::DidClose { params: p } => {
+                    self.documents.remove(&p.text_document.uri);
+                }
+
+                _ => {}
+            },
+
+            _ => {}
+        }
+    }
+
+    /// Use `request` as the `initialize` request to replay.
+    /// Used when the handshake was done without the client.
+    pub fn set_initialize(&mut self, request: Request) {
+        self.initialize = Some(request);
+    }
+
+    /// Record a message sent from the server to the client.
+    /// Returns `false` if the message should not be forwarded to the client.
+    pub fn server_message(&mut self, msg: &lsp::Message) -> bool {
+        match msg.response_id() {
+            Some(Id::String(id)) if id == REPLAY_INITIALIZE_ID => false,
+            Some(id) => {
+                self.pending.remove(id);
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Returns `true` if the server is expected to exit.
+    pub fn is_exiting(&self) -> bool {
+        self.exiting
+    }
+
+    /// Take IDs of the requests that will never be responded by the exited server.
+    pub fn take_pending(&mut self) -> Vec<Id> {
+        self.pending.drain().collect()
+    }
+
+    /// `initialize` request to send to a restarted server before anything else.
+    pub fn initialize_request(&self) -> Option<Request> {
+        match &self.initialize {
+            Some(Request::Initialize { id: _, params }) => Some(Request::Initialize {
+                id: Id::String(REPLAY_INITIALIZE_ID.to_owned()),
+                params: params.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Messages to send to a restarted server in order after it responded to `initialize`.
+    pub fn messages(&self) -> Vec<lsp::Message> {
+        let mut messages = Vec::with_capacity(self.documents.len() + 1);
+        if let Some(initialized) = &self.initialized {
+            messages.push(lsp::Message::Notification(initialized.clone()));
+        }
+        for doc in self.documents.values() {
+            messages.push(lsp::Message::Notification(Notification::DidOpen {
+                params: lsp_types::DidOpenTextDocumentParams {
+                    text_document: doc.clone(),
+                },
+            }));
+        }
+        messages
+    }
+}
+
+/// Responses to batches from the client, collected to send them back together.
+#[derive(Debug, Default)]
+pub struct Batches {
+    pending: Vec<Batch>,
+}
+
+#[derive(Debug)]
+struct Batch {
+    /// IDs of the requests in the batch waiting for the server to respond.
+    waiting: HashSet<Id>,
+    /// Responses received so far.
+    responses: Vec<lsp::Message>,
+}
+
+/// What became of a message given to `Batches::add`.
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Batched {
+    /// Not a response to a batch.
+    No(lsp::Message),
+    /// Kept until the rest of the batch is responded.
+    Held,
+    /// Completed the batch with these responses.
+    Complete(Vec<lsp::Message>),
+}
+
+impl Batches {
+    /// Wait for the responses to the requests `ids` in a batch, starting with `responses`
+    /// from the proxy. Returns the responses if there's nothing to wait for.
+    pub fn start(
+        &mut self,
+        ids: HashSet<Id>,
+        responses: Vec<lsp::Message>,
+    ) -> Option<Vec<lsp::Message>> {
+        if ids.is_empty() {
+            return (!responses.is_empty()).then_some(responses);
+        }
+        self.pending.push(Batch {
+            waiting: ids,
+            responses,
+        });
+        None
+    }
+
+    /// Add a message to the client to the batch it responds to, if any.
+    pub fn add(&mut self, msg: lsp::Message) -> Batched {
+        let id = match msg.response_id() {
+            Some(id) => id,
+            None => return Batched::No(msg),
+        };
+        let index = match self.pending.iter().position(|b| b.waiting.contains(id)) {
+            Some(index) => index,
+            None => return Batched::No(msg),
+        };
+        let batch = &mut self.pending[index];
+        batch.waiting.remove(id);
+        batch.responses.push(msg);
+        if batch.waiting.is_empty() {
+            Batched::Complete(self.pending.remove(index).responses)
+        } else {
+            Batched::Held
+        }
+    }
+
+    /// Returns `true` if no batches are waiting for responses.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Apply `textDocument/didChange` content change to `text`.
+fn apply_change(text: &mut String, change: &lsp_types::TextDocumentContentChangeEvent) {
+    if let Some(range) = change.range {
+        let start = offset_at(text, range.start);
+        let end = offset_at(text, range.end).max(start);
+        text.replace_range(start..end, &change.text);
+    } else {
+        *text = change.text.clone();
+    }
+}
+
+/// Byte offset of `position` in `text`. `position.character` is in UTF-16 code units.
+pub(super) fn offset_at(text: &str, position: lsp_types::Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+
+    use super::*;
+
+    fn change(
+        range: Option<((u32, u32), (u32, u32))>,
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: range.map(|(s, e)| Range::new(Position::new(s.0, s.1), Position::new(e.0, e.1))),
+            range_length: None,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let mut text = "fn main() {\n    foo();\n}\n".to_owned();
+        apply_change(&mut text, &change(Some(((1, 4), (1, 7))), "bar"));
+        assert_eq!(text, "fn main() {\n    bar();\n}\n");
+        apply_change(&mut text, &change(Some(((3, 0), (3, 0))), "// end\n"));
+        assert_eq!(text, "fn main() {\n    bar();\n}\n// end\n");
+        apply_change(&mut text, &change(None, "full"));
+        assert_eq!(text, "full");
+    }
+
+    #[test]
+    fn test_apply_change_utf16() {
+        let mut text = "let s = \"😀x\";".to_owned();
+        // The emoji is 2 UTF-16 code units.
+        apply_change(&mut text, &change(Some(((0, 11), (0, 12))), "y"));
+        assert_eq!(text, "let s = \"😀y\";");
+    }
+
+    fn response(id: i64) -> lsp::Message {
+        serde_json::from_value(serde_json::json!({"jsonrpc":"2.0","result":null,"id":id})).unwrap()
+    }
+
+    #[test]
+    fn test_open_documents() {
+        let message = |v: serde_json::Value| serde_json::from_value::<lsp::Message>(v).unwrap();
+        let open = |version: i32, text: &str| {
+            message(serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {"textDocument": {
+                    "uri": "file:///a.rs", "languageId": "rust", "version": version, "text": text,
+                }},
+            }))
+        };
+        let close = message(serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didClose",
+            "params": {"textDocument": {"uri": "file:///a.rs"}},
+        }));
+
+        let mut docs = OpenDocuments::default();
+        assert_eq!(docs.client_message(open(1, "a")), open(1, "a"));
+        // Opened again by a resuming client.
+        assert_eq!(
+            docs.client_message(open(2, "b")),
+            message(serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": {"uri": "file:///a.rs", "version": 2},
+                    "contentChanges": [{"text": "b"}],
+                },
+            }))
+        );
+        assert_eq!(docs.client_message(close.clone()), close);
+        assert_eq!(docs.client_message(open(3, "c")), open(3, "c"));
+    }
+
+    #[test]
+    fn test_batches() {
+        let mut batches = Batches::default();
+        assert_eq!(batches.start(HashSet::new(), Vec::new()), None);
+        assert_eq!(
+            batches.start(HashSet::new(), vec![response(1)]),
+            Some(vec![response(1)])
+        );
+
+        let ids = HashSet::from([Id::Number(2), Id::Number(3)]);
+        assert_eq!(batches.start(ids, vec![response(1)]), None);
+        assert_eq!(batches.add(response(4)), Batched::No(response(4)));
+        assert_eq!(batches.add(response(3)), Batched::Held);
+        assert_eq!(
+            batches.add(response(2)),
+            Batched::Complete(vec![response(1), response(3), response(2)])
+        );
+        assert!(batches.is_empty());
+    }
+}
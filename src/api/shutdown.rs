@@ -0,0 +1,113 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::{watch, Notify};
+
+/// Tells sessions to shut down, and waits for them to finish.
+#[derive(Debug)]
+pub struct Shutdown {
+    signal: watch::Sender<bool>,
+    /// Number of sessions alive.
+    active: AtomicUsize,
+    /// Notified when a session ended.
+    ended: Notify,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            signal: watch::channel(false).0,
+            active: AtomicUsize::new(0),
+            ended: Notify::new(),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Track a session until the returned guard is dropped.
+    pub fn track(self: &Arc<Self>) -> Guard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Guard(self.clone())
+    }
+
+    /// Tell all sessions to shut down.
+    pub fn request(&self) {
+        self.signal.send_replace(true);
+    }
+
+    /// Resolves when shutdown was requested, including before this was called.
+    pub fn requested(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut signal = self.signal.subscribe();
+        async move {
+            let _ = signal.wait_for(|requested| *requested).await;
+        }
+    }
+
+    /// Wait for all sessions to end.
+    pub async fn wait(&self) {
+        loop {
+            let ended = self.ended.notified();
+            tokio::pin!(ended);
+            ended.as_mut().enable();
+            let active = self.active.load(Ordering::SeqCst);
+            if active == 0 {
+                return;
+            }
+            tracing::info!("waiting for {} sessions to end", active);
+            ended.await;
+        }
+    }
+}
+
+/// Keeps the session counted as active. Stored in the session.
+#[derive(Debug)]
+pub struct Guard(Arc<Shutdown>);
+
+impl Guard {
+    /// Resolves when shutdown was requested.
+    pub fn requested(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.0.requested()
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+        self.0.ended.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_for_sessions() {
+        let shutdown = Arc::new(Shutdown::default());
+        let guard = shutdown.track();
+        let requested = guard.requested();
+        shutdown.request();
+        requested.await;
+        // Requested before subscribing
+        guard.requested().await;
+
+        let waiting = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.wait().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
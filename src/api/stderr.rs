@@ -0,0 +1,164 @@
+//! stderr of Language Servers.
+use std::time::{Duration, Instant};
+
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::ChildStderr,
+    sync::mpsc,
+};
+use tracing::Instrument;
+
+use crate::config::ForwardStderrConfig;
+
+/// Maximum number of lines waiting to be forwarded. Any more are dropped.
+const CHANNEL_SIZE: usize = 100;
+
+/// Lines from stderr of the server to forward to the client.
+#[derive(Debug)]
+pub struct Stderr(Option<mpsc::Receiver<String>>);
+
+impl Stderr {
+    /// Receive a line to forward. Pending forever if there's none to forward.
+    pub async fn next(&mut self) -> Option<String> {
+        match &mut self.0 {
+            Some(rx) => match rx.recv().await {
+                Some(line) => Some(line),
+                None => {
+                    self.0 = None;
+                    None
+                }
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    /// After the server exited, take the rest of the lines to forward.
+    pub async fn remaining(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(mut rx) = self.0.take() {
+            // Processes spawned by the server may keep stderr open.
+            let _ = tokio::time::timeout(Duration::from_secs(1), async {
+                while let Some(line) = rx.recv().await {
+                    lines.push(line);
+                }
+            })
+            .await;
+        }
+        lines
+    }
+}
+
+/// Log each line from `stderr` in `span` until it's closed.
+/// Lines are also forwarded to the client if `forward` is configured.
+pub fn capture(
+    stderr: ChildStderr,
+    span: tracing::Span,
+    forward: Option<&ForwardStderrConfig>,
+) -> Stderr {
+    let (tx, rx) = match forward {
+        Some(config) => {
+            let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+            (
+                Some((tx, RateLimit::new(config.max_lines_per_sec))),
+                Some(rx),
+            )
+        }
+        None => (None, None),
+    };
+    tokio::spawn(read_lines(stderr, tx).instrument(span));
+    Stderr(rx)
+}
+
+async fn read_lines(stderr: ChildStderr, mut forward: Option<(mpsc::Sender<String>, RateLimit)>) {
+    let mut lines = BufReader::new(stderr).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                tracing::warn!("failed to read stderr: {}", err);
+                break;
+            }
+        };
+        tracing::info!("{}", line);
+        if let Some((tx, limit)) = &mut forward {
+            match limit.check(Instant::now()) {
+                Admit::Yes { dropped } => {
+                    if dropped > 0 {
+                        let _ = tx.try_send(format!("({} lines from stderr dropped)", dropped));
+                    }
+                    // Nobody is receiving while detached or pooled, so drop when full.
+                    let _ = tx.try_send(line);
+                }
+                Admit::No => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Admit {
+    /// `dropped` is the number of lines dropped since the last one admitted.
+    Yes {
+        dropped: usize,
+    },
+    No,
+}
+
+/// Allows up to `max` lines in each second.
+#[derive(Debug)]
+struct RateLimit {
+    max: u32,
+    window_start: Option<Instant>,
+    count: u32,
+    dropped: usize,
+}
+
+impl RateLimit {
+    fn new(max: u32) -> Self {
+        Self {
+            max,
+            window_start: None,
+            count: 0,
+            dropped: 0,
+        }
+    }
+
+    fn check(&mut self, now: Instant) -> Admit {
+        let expired = self
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= Duration::from_secs(1));
+        if expired {
+            self.window_start = Some(now);
+            self.count = 0;
+        }
+        if self.count < self.max {
+            self.count += 1;
+            Admit::Yes {
+                dropped: std::mem::take(&mut self.dropped),
+            }
+        } else {
+            self.dropped += 1;
+            Admit::No
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limit = RateLimit::new(2);
+        let start = Instant::now();
+        assert_eq!(limit.check(start), Admit::Yes { dropped: 0 });
+        assert_eq!(limit.check(start), Admit::Yes { dropped: 0 });
+        assert_eq!(limit.check(start), Admit::No);
+        assert_eq!(limit.check(start + Duration::from_millis(500)), Admit::No);
+        assert_eq!(
+            limit.check(start + Duration::from_secs(1)),
+            Admit::Yes { dropped: 2 }
+        );
+    }
+}
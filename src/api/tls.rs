@@ -0,0 +1,278 @@
+//! TLS termination with certificates reloaded when the files change.
+use std::{
+    io,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use futures_util::{stream, Stream};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        crypto::ring::default_provider,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+use crate::config::TlsConfig;
+
+/// Time allowed to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts TLS connections with the current certificate.
+#[derive(Debug)]
+pub struct Tls {
+    config: TlsConfig,
+    current: RwLock<Arc<rustls::ServerConfig>>,
+    /// Modification times of the files the current config was loaded from.
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl Tls {
+    pub fn new(config: TlsConfig) -> Result<Arc<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        let modified = modified_times(&config);
+        let current = load(&config)?;
+        Ok(Arc::new(Self {
+            config,
+            current: RwLock::new(Arc::new(current)),
+            modified: Mutex::new(modified),
+        }))
+    }
+
+    /// Check the files for changes periodically, and use the new certificate for
+    /// new connections. Established connections are not affected.
+    pub fn watch(self: &Arc<Self>) {
+        let tls = Arc::downgrade(self);
+        let interval = Duration::from_secs(self.config.reload_interval_secs.max(1));
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                match tls.upgrade() {
+                    Some(tls) => tls.reload_if_changed(),
+                    None => break,
+                }
+            }
+        });
+    }
+
+    fn reload_if_changed(&self) {
+        let modified = modified_times(&self.config);
+        {
+            let mut last = self.modified.lock().unwrap();
+            if *last == modified {
+                return;
+            }
+            // Files can be replaced one at a time. Retry on the next change if it fails.
+            *last = modified;
+        }
+        match load(&self.config) {
+            Ok(config) => {
+                *self.current.write().unwrap() = Arc::new(config);
+                tracing::info!("reloaded TLS certificate {}", self.config.cert_path);
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "failed to reload TLS certificate, keeping the current: {}",
+                    err
+                );
+            }
+        }
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    /// Accept connections from `listener` and complete the handshake.
+    /// Connections failing the handshake are dropped.
+    pub fn incoming(
+        self: Arc<Self>,
+        listener: TcpListener,
+    ) -> impl Stream<Item = Result<TlsStream<TcpStream>, io::Error>> + Send {
+        let (tx, mut rx) = mpsc::channel(32);
+        tokio::spawn(async move {
+            loop {
+                let (tcp, addr) = tokio::select! {
+                    // Stop listening when the server stopped accepting.
+                    _ = tx.closed() => break,
+                    res = listener.accept() => match res {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            tracing::warn!("failed to accept connection: {}", err);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    },
+                };
+                let acceptor = self.acceptor();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send(stream).await;
+                        }
+                        Ok(Err(err)) => {
+                            tracing::debug!("TLS handshake with {} failed: {}", addr, err)
+                        }
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+        stream::poll_fn(move |cx| rx.poll_recv(cx).map(|s| s.map(Ok)))
+    }
+}
+
+fn modified_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    std::iter::once(&config.cert_path)
+        .chain(std::iter::once(&config.key_path))
+        .chain(config.client_ca_path.as_ref())
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+fn load(
+    config: &TlsConfig,
+) -> Result<rustls::ServerConfig, Box<dyn std::error::Error + Send + Sync>> {
+    let certs = read_certs(&config.cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)
+        .map_err(|e| format!("failed to read key {}: {}", config.key_path, e))?;
+
+    let provider = Arc::new(default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots.add(cert)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid certificate {}: {}", config.cert_path, e))?;
+    // WebSocket upgrades require HTTP/1.1.
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to read certificates {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates in {}", path));
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, fs::File, path::Path};
+
+    use futures_util::StreamExt;
+    use tokio_rustls::{rustls::pki_types::ServerName, TlsConnector};
+
+    use super::*;
+
+    const CA: &str = include_str!("testdata/tls/ca.pem");
+    const A: (&str, &str) = (
+        include_str!("testdata/tls/a.pem"),
+        include_str!("testdata/tls/a.key"),
+    );
+    const B: (&str, &str) = (
+        include_str!("testdata/tls/b.pem"),
+        include_str!("testdata/tls/b.key"),
+    );
+
+    fn write_pair(dir: &Path, (cert, key): (&str, &str), modified: SystemTime) {
+        for (name, contents) in [("cert.pem", cert), ("key.pem", key)] {
+            std::fs::write(dir.join(name), contents).unwrap();
+            // Make the change visible even if the clock is too coarse.
+            File::options()
+                .write(true)
+                .open(dir.join(name))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+    }
+
+    /// Do the handshake with the next connection from `incoming`, and return the certificate
+    /// the server presented.
+    async fn peer_certificate<S>(
+        addr: std::net::SocketAddr,
+        incoming: &mut S,
+    ) -> CertificateDer<'static>
+    where
+        S: Stream<Item = Result<TlsStream<TcpStream>, io::Error>> + Unpin,
+    {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_slice(CA.as_bytes()).unwrap())
+            .unwrap();
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let connect = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp);
+        let (client, server) = tokio::join!(connect, incoming.next());
+        server.unwrap().unwrap();
+        client.unwrap().get_ref().1.peer_certificates().unwrap()[0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_reload_changed_certificate() {
+        let dir = std::env::temp_dir().join(format!("lsp-ws-proxy-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        write_pair(&dir, A, now);
+        let tls = Tls::new(TlsConfig {
+            cert_path: dir.join("cert.pem").to_string_lossy().into_owned(),
+            key_path: dir.join("key.pem").to_string_lossy().into_owned(),
+            client_ca_path: None,
+            reload_interval_secs: 1,
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut incoming = Box::pin(tls.clone().incoming(listener));
+
+        let a = CertificateDer::from_pem_slice(A.0.as_bytes()).unwrap();
+        let b = CertificateDer::from_pem_slice(B.0.as_bytes()).unwrap();
+        assert_eq!(peer_certificate(addr, &mut incoming).await, a);
+
+        // Unchanged files are not reloaded.
+        tls.reload_if_changed();
+        assert_eq!(peer_certificate(addr, &mut incoming).await, a);
+
+        write_pair(&dir, B, now + Duration::from_secs(60));
+        tls.reload_if_changed();
+        assert_eq!(peer_certificate(addr, &mut incoming).await, b);
+
+        // A broken key keeps the current certificate.
+        write_pair(&dir, (A.0, "broken"), now + Duration::from_secs(120));
+        tls.reload_if_changed();
+        assert_eq!(peer_certificate(addr, &mut incoming).await, b);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use url::Url;
+
+use crate::config::WorkspaceConfig;
+
+/// Directory a session works in.
+#[derive(Debug)]
+pub struct Workspace {
+    pub path: PathBuf,
+    /// `path` as a directory URI. Relative `source://` URIs are remapped under this.
+    pub uri: Url,
+    /// Set if the directory was created for the session.
+    isolated: Option<(String, Arc<Workspaces>)>,
+}
+
+impl Workspace {
+    /// Workspace shared by all sessions.
+    pub fn shared(cwd: &Url) -> Self {
+        Self {
+            path: cwd.to_file_path().expect("valid path from cwd"),
+            uri: cwd.clone(),
+            isolated: None,
+        }
+    }
+
+    /// Returns `true` if the directory was created for the session.
+    pub fn is_isolated(&self) -> bool {
+        self.isolated.is_some()
+    }
+
+    /// Remove the directory if created for the session.
+    /// The directory is kept until `retain_secs` passes if configured.
+    pub async fn cleanup(self) -> Result<(), std::io::Error> {
+        let Self { path, isolated, .. } = self;
+        if let Some((id, workspaces)) = isolated {
+            let name = {
+                let mut dirs = workspaces.dirs.lock().unwrap();
+                let (name, _) = dirs.remove(&id).expect("active workspace");
+                // Keep the name taken until removed.
+                if workspaces.config.retain_secs.is_some() {
+                    workspaces.retained.lock().unwrap().insert(name.clone());
+                }
+                name
+            };
+            if let Some(secs) = workspaces.config.retain_secs {
+                tracing::info!("retaining workspace {:?} for {}s", path, secs);
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(secs)).await;
+                    if let Err(err) = remove_dir(&path).await {
+                        tracing::error!("failed to remove workspace {:?}: {}", path, err);
+                    }
+                    workspaces.retained.lock().unwrap().remove(&name);
+                });
+            } else {
+                remove_dir(&path).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn remove_dir(path: &Path) -> Result<(), std::io::Error> {
+    tracing::debug!("removing workspace {:?}", path);
+    tokio::fs::remove_dir_all(path).await
+}
+
+/// Workspaces created for each session, keyed by the session ID.
+#[derive(Debug)]
+pub struct Workspaces {
+    config: WorkspaceConfig,
+    root: PathBuf,
+    /// Name and path of the workspace of each session.
+    dirs: Mutex<HashMap<String, (String, PathBuf)>>,
+    /// Names of the workspaces kept after the session ended.
+    retained: Mutex<HashSet<String>>,
+}
+
+impl Workspaces {
+    pub fn new(config: WorkspaceConfig) -> Self {
+        let root = config
+            .root
+            .as_ref()
+            .map_or_else(|| std::env::temp_dir().join("lsp-ws-proxy"), PathBuf::from);
+        Self {
+            config,
+            root,
+            dirs: Mutex::new(HashMap::new()),
+            retained: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Create a workspace `name` for the session `id`, copying the template if configured.
+    /// Fails if the workspace is used by another session.
+    pub async fn create(
+        self: &Arc<Self>,
+        id: &str,
+        name: &str,
+    ) -> Result<Workspace, std::io::Error> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid workspace name {:?}", name),
+            ));
+        }
+        let path = self.root.join(name);
+        {
+            let mut dirs = self.dirs.lock().unwrap();
+            if dirs.values().any(|(n, _)| n == name) || self.retained.lock().unwrap().contains(name)
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("workspace {:?} is in use", name),
+                ));
+            }
+            // Reserve the name until created.
+            dirs.insert(id.to_owned(), (name.to_owned(), path.clone()));
+        }
+        match self.create_dir(&path).await {
+            Ok(path) => {
+                let uri = Url::from_directory_path(&path).expect("valid url from workspace");
+                self.dirs
+                    .lock()
+                    .unwrap()
+                    .insert(id.to_owned(), (name.to_owned(), path.clone()));
+                Ok(Workspace {
+                    path,
+                    uri,
+                    isolated: Some((id.to_owned(), self.clone())),
+                })
+            }
+            Err(err) => {
+                self.dirs.lock().unwrap().remove(id);
+                Err(err)
+            }
+        }
+    }
+
+    async fn create_dir(&self, path: &Path) -> Result<PathBuf, std::io::Error> {
+        tracing::debug!("creating workspace {:?}", path);
+        tokio::fs::create_dir_all(path).await?;
+        if let Some(template) = &self.config.template {
+            let from = PathBuf::from(template);
+            let to = path.to_owned();
+            let copied = tokio::task::spawn_blocking(move || copy_dir(&from, &to))
+                .await
+                .expect("copy task not to panic");
+            if let Err(err) = copied {
+                let _ = remove_dir(path).await;
+                return Err(err);
+            }
+        }
+        // Canonicalize so that paths in URIs from the server match.
+        tokio::fs::canonicalize(path).await
+    }
+
+    /// Name and path of the workspace of the session `id` if it's active.
+    pub fn get(&self, id: &str) -> Option<(String, PathBuf)> {
+        self.dirs.lock().unwrap().get(id).cloned()
+    }
+}
+
+/// Recursively copy the contents of directory `from` into `to`.
+fn copy_dir(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dst = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&dst)?;
+            copy_dir(&entry.path(), &dst)?;
+        } else {
+            std::fs::copy(entry.path(), &dst)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_create_and_cleanup() {
+        let tmp = std::env::temp_dir().join(format!("lsp-ws-proxy-test-{}", uuid::Uuid::new_v4()));
+        let template = tmp.join("template");
+        std::fs::create_dir_all(template.join("src")).unwrap();
+        std::fs::write(template.join("src/main.rs"), "fn main() {}").unwrap();
+
+        let workspaces = Arc::new(Workspaces::new(WorkspaceConfig {
+            root: Some(tmp.join("sessions").to_str().unwrap().to_owned()),
+            template: Some(template.to_str().unwrap().to_owned()),
+            retain_secs: None,
+        }));
+        let workspace = workspaces.create("abc", "ws").await.unwrap();
+        assert!(workspace.is_isolated());
+        assert_eq!(
+            std::fs::read_to_string(workspace.path.join("src/main.rs")).unwrap(),
+            "fn main() {}"
+        );
+        assert_eq!(
+            workspaces.get("abc"),
+            Some(("ws".to_owned(), workspace.path.clone()))
+        );
+        // The name is taken.
+        assert!(workspaces.create("def", "ws").await.is_err());
+        assert!(workspaces.create("def", "../ws").await.is_err());
+
+        let path = workspace.path.clone();
+        workspace.cleanup().await.unwrap();
+        assert!(!path.exists());
+        assert_eq!(workspaces.get("abc"), None);
+        std::fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+//! `check` and `probe` subcommands to find misconfigured servers before clients do.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use futures_util::SinkExt;
+use url::Url;
+
+use crate::{
+    api::server::{Launch, Placeholders, Server},
+    config::{Config, ServerConfig},
+    lsp::{self, types::Id},
+};
+
+/// How long to wait to connect to each database.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Servers in `config` sorted by name, followed by the ones from the command line.
+fn servers(
+    config: Option<&Config>,
+    commands: Option<&[Vec<String>]>,
+) -> Vec<(String, ServerConfig)> {
+    let mut servers = config
+        .and_then(|c| c.servers.as_ref())
+        .into_iter()
+        .flatten()
+        .map(|(name, server)| (name.clone(), server.clone()))
+        .collect::<Vec<_>>();
+    servers.sort_by(|a, b| a.0.cmp(&b.0));
+    servers.extend(
+        commands
+            .into_iter()
+            .flatten()
+            .map(|c| (c[0].clone(), ServerConfig::from_command(c.clone()))),
+    );
+    servers
+}
+
+/// Check that the command of each server is found, and that the databases for `sql`
+/// accept the admin credentials. Returns the number of problems found.
+pub async fn check(config: Option<&Config>, commands: Option<&[Vec<String>]>) -> usize {
+    let mut problems = 0;
+    let servers = servers(config, commands);
+    if servers.is_empty() {
+        println!("failed  no servers configured");
+        problems += 1;
+    }
+    for (name, server) in &servers {
+        match find_program(server) {
+            Ok(Some(path)) => println!("ok      server {}: {}", name, path.display()),
+            Ok(None) => println!(
+                "skipped server {}: {} is resolved for each connection",
+                name, server.command[0]
+            ),
+            Err(err) => {
+                println!("failed  server {}: {}", name, err);
+                problems += 1;
+            }
+        }
+    }
+
+    let mut sql = config
+        .and_then(|c| c.sql.as_ref())
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    sql.sort_by(|a, b| a.0.cmp(b.0));
+    for (driver, sql_config) in sql {
+        if driver == "sqlite" {
+            println!("skipped sql sqlite: databases are files created for each session");
+            continue;
+        }
+        let target = format!(
+            "{}@{}:{}",
+            sql_config.admin_username, sql_config.host, sql_config.port
+        );
+        let connect = lsp::ext::sqls_check_admin_connection(driver, sql_config);
+        match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+            Ok(Ok(())) => println!("ok      sql {}: {}", driver, target),
+            Ok(Err(err)) => {
+                println!("failed  sql {}: {}: {}", driver, target, err);
+                problems += 1;
+            }
+            Err(_) => {
+                println!("failed  sql {}: {}: timed out", driver, target);
+                problems += 1;
+            }
+        }
+    }
+    problems
+}
+
+/// Find the program of `server` like spawning it would.
+/// Returns `None` if it can't be known before a connection.
+fn find_program(server: &ServerConfig) -> Result<Option<PathBuf>, String> {
+    let program = &server.command[0];
+    // Placeholders are left as is until the connection.
+    if program.contains('$') {
+        return Ok(None);
+    }
+    let path = Path::new(program);
+    let is_path = path.components().count() > 1;
+    // The server is started in `cwd`, so relative paths are resolved from there.
+    let cwd = match server.cwd.as_deref() {
+        Some(cwd) if cwd.contains('$') => {
+            if is_path && path.is_relative() {
+                return Ok(None);
+            }
+            None
+        }
+        cwd => cwd.map(Path::new),
+    };
+    let resolve = |path: &Path| match cwd {
+        Some(cwd) => cwd.join(path),
+        None => path.to_owned(),
+    };
+    if is_path {
+        let path = resolve(path);
+        return if is_executable(&path) {
+            Ok(Some(path))
+        } else {
+            Err(format!("{} is not an executable file", path.display()))
+        };
+    }
+
+    let paths = match server.env.get("PATH") {
+        Some(paths) => paths.into(),
+        None if server.clear_env => {
+            return Err(format!(
+                "{} can't be found without PATH in env when clear_env is set",
+                program
+            ))
+        }
+        None => std::env::var_os("PATH").unwrap_or_default(),
+    };
+    std::env::split_paths(&paths)
+        .map(|dir| resolve(&dir).join(program))
+        .find(|path| is_executable(path))
+        .map(Some)
+        .ok_or_else(|| format!("{} is not found in PATH", program))
+}
+
+fn is_executable(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        #[cfg(unix)]
+        Ok(metadata) => {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+        }
+        #[cfg(not(unix))]
+        Ok(metadata) => metadata.is_file(),
+        Err(_) => false,
+    }
+}
+
+/// Start the server `name` in `cwd`, do the `initialize` and `shutdown` handshake,
+/// and print the capabilities and how long each step took.
+pub async fn probe(
+    name: &str,
+    config: Option<&Config>,
+    commands: Option<&[Vec<String>]>,
+    cwd: &Url,
+    query: &HashMap<String, String>,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (_, server_config) = servers(config, commands)
+        .into_iter()
+        .find(|(n, _)| n == name)
+        .ok_or_else(|| format!("no server named {:?}", name))?;
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let workspace = cwd.to_file_path().expect("valid path from cwd");
+    let launch = Launch::new(
+        name,
+        &server_config,
+        &Placeholders {
+            session_id: &session_id,
+            workspace: workspace
+                .to_str()
+                .ok_or_else(|| format!("workspace path {:?} is not valid UTF-8", workspace))?,
+            query,
+        },
+    )?;
+
+    let started = Instant::now();
+    let mut server = Server::spawn(&launch)
+        .map_err(|e| format!("failed to start {}: {}", launch.command[0], e))?;
+    let request = lsp::Request::Initialize {
+        id: Id::Number(1),
+        params: serde_json::from_value(serde_json::json!({
+            "processId": std::process::id(),
+            "rootUri": cwd,
+            "capabilities": {},
+        }))?,
+    };
+    let result = tokio::time::timeout(timeout, server.initialize(&request))
+        .await
+        .map_err(|_| "timed out waiting for initialize response")??;
+    let initialize_time = started.elapsed();
+    let initialized = lsp::Message::Notification(lsp::Notification::Initialized {
+        params: lsp_types::InitializedParams {},
+    });
+    server
+        .send
+        .send(serde_json::to_string(&initialized)?)
+        .await?;
+
+    let shutdown_started = Instant::now();
+    let status = tokio::time::timeout(timeout, server.shutdown_and_wait())
+        .await
+        .map_err(|_| "timed out waiting for the server to exit")??;
+    let shutdown_time = shutdown_started.elapsed();
+
+    if let Some(info) = result.get("serverInfo") {
+        println!("server: {}", serde_json::to_string(info)?);
+    }
+    println!(
+        "capabilities: {}",
+        serde_json::to_string_pretty(result.get("capabilities").unwrap_or(&result))?
+    );
+    println!("initialize: {:?}", initialize_time);
+    println!("shutdown: {:?} ({})", shutdown_time, status);
+    Ok(())
+}
+
+/// Parse `<name>=<value>` for `${query.<name>}` placeholders.
+pub fn parse_query_param(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .ok_or_else(|| format!("{} is not <name>=<value>", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_program() {
+        let server = |command: &[&str]| {
+            ServerConfig::from_command(command.iter().map(|s| s.to_string()).collect())
+        };
+        assert_eq!(
+            find_program(&server(&["/bin/sh"])),
+            Ok(Some(PathBuf::from("/bin/sh")))
+        );
+        assert!(find_program(&server(&["/nonexistent/sh"])).is_err());
+        assert_eq!(find_program(&server(&["${query.ls}"])), Ok(None));
+
+        let mut sh = server(&["sh"]);
+        sh.env
+            .insert("PATH".to_owned(), "/nonexistent:/bin".to_owned());
+        assert_eq!(find_program(&sh), Ok(Some(PathBuf::from("/bin/sh"))));
+        sh.env.clear();
+        sh.clear_env = true;
+        assert!(find_program(&sh).is_err());
+
+        // Relative to `cwd` of the server.
+        let dir = std::env::temp_dir().join(format!("lsp-ws-proxy-check-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::copy("/bin/sh", dir.join("bin/tool")).unwrap();
+        let mut tool = server(&["bin/tool"]);
+        assert!(find_program(&tool).is_err());
+        tool.cwd = Some(dir.to_string_lossy().into_owned());
+        assert_eq!(find_program(&tool), Ok(Some(dir.join("bin/tool"))));
+        let mut tool_in_path = server(&["tool"]);
+        tool_in_path.cwd = tool.cwd.clone();
+        tool_in_path
+            .env
+            .insert("PATH".to_owned(), "/nonexistent:bin".to_owned());
+        assert_eq!(find_program(&tool_in_path), Ok(Some(dir.join("bin/tool"))));
+        tool.cwd = Some("${workspace}".to_owned());
+        assert_eq!(find_program(&tool), Ok(None));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            parse_query_param("jdk=jdk-21"),
+            Ok(("jdk".to_owned(), "jdk-21".to_owned()))
+        );
+        assert!(parse_query_param("jdk").is_err());
+    }
+}
@@ -1,8 +1,13 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, fmt, path::Path};
 
-use serde::{Deserialize, Serialize};
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+/// Drivers supported by `sql`.
+const SQL_DRIVERS: &[&str] = &["mysql", "postgres", "sqlite"];
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     // When true, the server will return a 404 error if the query parameter `name` is not found.
     // If false, it will return the first server in the list.
@@ -16,19 +21,776 @@ pub struct Config {
     pub servers: Option<HashMap<String, ServerConfig>>,
     // key is driver name, value is the SQL configuration.
     // Supported drivers are: mysql, postgres, sqlite.
+    #[serde(default, deserialize_with = "deserialize_sql")]
+    #[schemars(extend("propertyNames" = { "enum": SQL_DRIVERS }))]
     pub sql: Option<HashMap<String, SqlConfig>>,
+    // Keep the server running after the client disconnects so that the client
+    // can reconnect and resume the session. Disabled when not present.
+    #[serde(default)]
+    pub resume: Option<ResumeConfig>,
+    // Create a fresh workspace directory for each session instead of sharing
+    // the current directory. Disabled when not present.
+    #[serde(default)]
+    pub workspace: Option<WorkspaceConfig>,
+    // Maximum number of concurrent connections. Unlimited when not present.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    // Let connections over the limits wait in a queue.
+    // Connections are rejected with 503 when not present.
+    #[serde(default)]
+    pub queue: Option<QueueConfig>,
+    // Shut down the server and close the connection when the client hasn't sent
+    // any LSP messages for this many seconds. Disabled when not present.
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+    // Seconds to wait for sessions to shut down on SIGTERM before exiting.
+    // Defaults to 30.
+    #[serde(default)]
+    pub shutdown_timeout_secs: Option<u64>,
+    // Require a token to connect and to use `/files`.
+    // No authentication when not present.
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    // Origins allowed to use the proxy from browsers, including WebSocket connections.
+    // Any origin is allowed when not present.
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    // Serve `wss://` and `https://` with the certificate. Plain HTTP when not present.
+    // `--tls-cert` and `--tls-key` take precedence over the paths.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    // Limit the rate of LSP messages from each session and of requests to `/files`.
+    // Not limited when not present.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// Read the config from `file_path`, expanding environment variables.
+/// Unset variables are errors unless the default is given like `${VAR:-default}`.
+/// The format is YAML for `.yaml` and `.yml`, TOML for `.toml`, and JSON otherwise.
+/// Errors are prefixed with `<file_path>:<line>:<column>` when the location is known.
+pub fn read_config_from_file(file_path: &str) -> Result<Config, String> {
+    let raw = std::fs::read_to_string(file_path).map_err(|e| e.to_string())?;
+    let rendered = shellexpand::env_with_context(&raw, |s| {
+        // Keep placeholders expanded for each connection.
+        if is_placeholder(s) {
+            return Ok(None);
+        }
+        std::env::var(s).map(Some)
+    })
+    .map_err(|e| {
+        let message = match &e.cause {
+            std::env::VarError::NotPresent => format!(
+                "environment variable {} is not set, use ${{{}:-<default>}} to allow it",
+                e.var_name, e.var_name
+            ),
+            cause => format!("environment variable {}: {}", e.var_name, cause),
+        };
+        // The first reference to the variable.
+        match ["${", "$"]
+            .iter()
+            .find_map(|prefix| raw.find(&format!("{}{}", prefix, e.var_name)))
+        {
+            Some(offset) => {
+                let (line, column) = line_column(&raw, offset);
+                format!("{}:{}:{}: {}", file_path, line, column, message)
+            }
+            None => format!("{}: {}", file_path, message),
+        }
+    })?;
+    let config =
+        parse_config(&rendered, Format::from_path(file_path)).map_err(|e| match e.location {
+            Some((line, column)) => format!("{}:{}:{}: {}", file_path, line, column, e.message),
+            None => format!("{}: {}", file_path, e.message),
+        })?;
+    config
+        .validate()
+        .map_err(|e| format!("{}: {}", file_path, e))?;
+    Ok(config)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("toml") => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ParseError {
+    message: String,
+    /// 1-based line and column.
+    location: Option<(usize, usize)>,
+}
+
+fn parse_config(text: &str, format: Format) -> Result<Config, ParseError> {
+    match format {
+        Format::Json => serde_json::from_str(text).map_err(|e| {
+            let location = (e.line() > 0).then(|| (e.line(), e.column()));
+            ParseError {
+                message: strip_location(e.to_string(), location),
+                location,
+            }
+        }),
+        Format::Yaml => serde_yaml::from_str(text).map_err(|e| {
+            let location = e.location().map(|l| (l.line(), l.column()));
+            ParseError {
+                message: strip_location(e.to_string(), location),
+                location,
+            }
+        }),
+        Format::Toml => toml::from_str(text).map_err(|e| ParseError {
+            message: e.message().to_owned(),
+            location: e.span().map(|span| line_column(text, span.start)),
+        }),
+    }
+}
+
+/// Remove the location appended to the messages by `serde_json` and `serde_yaml`.
+fn strip_location(message: String, location: Option<(usize, usize)>) -> String {
+    match location {
+        Some((line, column)) => {
+            let suffix = format!(" at line {} column {}", line, column);
+            message
+                .strip_suffix(&suffix)
+                .map_or_else(|| message.clone(), str::to_owned)
+        }
+        None => message,
+    }
+}
+
+/// 1-based line and column of the byte `offset` in `text`.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn deserialize_command<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    let command = Vec::<String>::deserialize(d)?;
+    if command.is_empty() {
+        return Err(D::Error::custom("command is empty"));
+    }
+    Ok(command)
+}
+
+fn deserialize_sql<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<HashMap<String, SqlConfig>>, D::Error> {
+    // Check the driver as a key to report the location of the unsupported one.
+    #[derive(PartialEq, Eq, Hash)]
+    struct Driver(String);
+
+    impl<'de> Deserialize<'de> for Driver {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            let name = String::deserialize(d)?;
+            if !SQL_DRIVERS.contains(&name.as_str()) {
+                return Err(D::Error::unknown_variant(&name, SQL_DRIVERS));
+            }
+            Ok(Self(name))
+        }
+    }
+
+    let sql = Option::<HashMap<Driver, SqlConfig>>::deserialize(d)?;
+    Ok(sql.map(|sql| sql.into_iter().map(|(k, v)| (k.0, v)).collect()))
+}
+
+/// A value in the config that must not be shown, like passwords.
+/// `file:<path>` reads the value from the file, e.g., Docker and Kubernetes secrets
+/// mounted at `/run/secrets/<name>`, ignoring the trailing newline.
+/// Shown as `..` in `Debug` and serialized as `[redacted]`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    #[cfg(test)]
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+// Manually implemented to avoid showing the secret.
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str("[redacted]")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(d)?;
+        match value.strip_prefix("file:") {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|s| Self(s.trim_end_matches(['\r', '\n']).to_owned()))
+                .map_err(|e| D::Error::custom(format!("failed to read secret {}: {}", path, e))),
+            None => Ok(Self(value)),
+        }
+    }
+}
+
+impl JsonSchema for Secret {
+    fn schema_name() -> Cow<'static, str> {
+        "Secret".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "The value, or `file:<path>` to read it from the file.",
+        })
+    }
+}
+
+/// Notifications the server must receive all of to know the contents of the documents.
+const DOCUMENT_SYNC_METHODS: &[&str] = &[
+    "textDocument/didOpen",
+    "textDocument/didChange",
+    "textDocument/didClose",
+    "textDocument/didSave",
+    "notebookDocument/didOpen",
+    "notebookDocument/didChange",
+    "notebookDocument/didClose",
+    "notebookDocument/didSave",
+];
+
+impl Config {
+    /// Check what can't be expressed by the types.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut methods = self.rate_limit.iter().flat_map(|r| r.methods.keys());
+        if let Some(method) = methods.find(|m| DOCUMENT_SYNC_METHODS.contains(&m.as_str())) {
+            return Err(format!(
+                "rate_limit.methods.{}: document synchronization can't be limited",
+                method
+            ));
+        }
+        let limits = self.rate_limit.iter().flat_map(|r| {
+            r.methods
+                .iter()
+                .map(|(method, limit)| (format!("rate_limit.methods.{}", method), limit))
+                .chain(
+                    r.files
+                        .iter()
+                        .map(|limit| ("rate_limit.files".to_owned(), limit)),
+                )
+        });
+        for (key, limit) in limits {
+            if !(limit.per_sec.is_finite() && limit.per_sec > 0.0) {
+                return Err(format!("{}: per_sec must be positive", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the settings changed in `other` that are only read on start.
+    pub fn changes_requiring_restart(&self, other: &Config) -> Vec<String> {
+        let old = serde_json::to_value(self).unwrap_or_default();
+        let new = serde_json::to_value(other).unwrap_or_default();
+        let mut changed = [
+            "workspace",
+            "max_connections",
+            "queue",
+            "shutdown_timeout_secs",
+            "cors",
+            "tls",
+        ]
+        .iter()
+        .filter(|key| old.get(key) != new.get(key))
+        .map(|key| key.to_string())
+        .collect::<Vec<_>>();
+        // Compared directly because secrets are redacted in JSON.
+        if self.auth != other.auth {
+            changed.push("auth".to_owned());
+        }
+        if old.pointer("/rate_limit/files") != new.pointer("/rate_limit/files") {
+            changed.push("rate_limit.files".to_owned());
+        }
+
+        let server = |config: &serde_json::Value, name: &str, key: &str| {
+            config
+                .get("servers")
+                .and_then(|servers| servers.get(name))
+                .and_then(|server| server.get(key))
+                .cloned()
+                .filter(|value| !value.is_null())
+        };
+        let mut names = self
+            .servers
+            .iter()
+            .chain(other.servers.iter())
+            .flat_map(|servers| servers.keys())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        for name in names {
+            for key in ["pool", "max_instances"] {
+                if server(&old, name, key) != server(&new, name, key) {
+                    changed.push(format!("servers.{}.{}", name, key));
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
+    // Command and arguments to start the server.
+    // Placeholders `${session_id}`, `${workspace}` and `${query.<name>}` are expanded
+    // for each connection. Connections without the query parameter used are rejected.
+    #[serde(deserialize_with = "deserialize_command")]
+    #[schemars(length(min = 1))]
     pub command: Vec<String>,
+    // Additional environment variables. Values can use placeholders.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    // Working directory of the server. Can use placeholders.
+    // Defaults to the workspace of the session.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    // Don't inherit the environment of the proxy.
+    // Note that `PATH` needs to be set in `env` to find commands by name.
+    #[serde(default)]
+    pub clear_env: bool,
+    // Restart the server when it exits unexpectedly.
+    // The server is not restarted when this is not present.
+    #[serde(default)]
+    pub restart: Option<RestartConfig>,
+    // Keep processes spawned ahead of connections.
+    #[serde(default)]
+    pub pool: Option<PoolConfig>,
+    // Resource limits of the server process. Only supported on Unix.
+    #[serde(default)]
+    pub limits: Option<LimitsConfig>,
+    // Maximum number of running instances, including the ones in detached sessions.
+    // Unlimited when not present.
+    #[serde(default)]
+    pub max_instances: Option<usize>,
+    // Forward lines from stderr of the server to the client as `window/logMessage`.
+    // Lines are only logged by the proxy when not present.
+    #[serde(default)]
+    pub forward_stderr: Option<ForwardStderrConfig>,
+    // Forward JSON-RPC batches from the client to the server as is.
+    // Batches are split into separate messages by default because LSP doesn't use them.
+    #[serde(default)]
+    pub batch: bool,
+}
+
+impl ServerConfig {
+    pub fn from_command(command: Vec<String>) -> Self {
+        Self {
+            command,
+            env: HashMap::new(),
+            cwd: None,
+            clear_env: false,
+            restart: None,
+            pool: None,
+            limits: None,
+            max_instances: None,
+            forward_stderr: None,
+            batch: false,
+        }
+    }
+}
+
+/// Returns `true` if `name` is a placeholder expanded for each connection
+/// instead of an environment variable.
+pub fn is_placeholder(name: &str) -> bool {
+    matches!(name, "session_id" | "workspace") || name.starts_with("query.")
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RestartConfig {
+    // Maximum number of restarts in a session.
+    pub max_restarts: u32,
+    // Delay before the first restart in milliseconds.
+    // Doubled after each restart up to `max_backoff_ms`.
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+impl RestartConfig {
+    // Delay before the restart `n` (0-based).
+    pub fn backoff(&self, n: u32) -> std::time::Duration {
+        let ms = self
+            .backoff_ms
+            .saturating_mul(1u64.checked_shl(n).unwrap_or(u64::MAX));
+        std::time::Duration::from_millis(ms.min(self.max_backoff_ms))
+    }
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PoolConfig {
+    // Number of idle processes to keep.
+    pub size: usize,
+    // Replace processes idle for longer than this many seconds.
+    // Idle processes are kept forever when not present.
+    #[serde(default)]
+    pub idle_ttl_secs: Option<u64>,
+    // Send `initialize` to the pooled processes before handing them out.
+    // The `initialize` request from the client is answered with the cached result
+    // and its params are ignored, so this should only be enabled for servers that
+    // don't depend on them.
+    #[serde(default)]
+    pub pre_initialize: bool,
+    // Params of the `initialize` request used for pre-initialization.
+    // Defaults to `{"processId": null, "rootUri": <cwd>, "capabilities": {}}`.
+    #[serde(default)]
+    pub initialize_params: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardStderrConfig {
+    // Maximum number of lines to forward each second. Any more are dropped.
+    #[serde(default = "default_max_lines_per_sec")]
+    pub max_lines_per_sec: u32,
+}
+
+fn default_max_lines_per_sec() -> u32 {
+    10
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    // Maximum size of the virtual memory in bytes (`RLIMIT_AS`).
+    #[serde(default)]
+    pub address_space_bytes: Option<u64>,
+    // Maximum CPU time in seconds (`RLIMIT_CPU`).
+    #[serde(default)]
+    pub cpu_secs: Option<u64>,
+    // Maximum number of open files (`RLIMIT_NOFILE`).
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    // Maximum number of processes of the user (`RLIMIT_NPROC`).
+    #[serde(default)]
+    pub processes: Option<u64>,
+    // Nice value of the process.
+    #[serde(default)]
+    pub nice: Option<i32>,
+    // cgroup v2 directory to create a cgroup for each server process in.
+    // Must be writable by the user running the proxy. Linux only.
+    #[serde(default)]
+    pub cgroup: Option<String>,
+    // Maximum memory usage of the cgroup in bytes (`memory.max`). Requires `cgroup`.
+    // Unlike `address_space_bytes`, this includes child processes.
+    #[serde(default)]
+    pub memory_max_bytes: Option<u64>,
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30_000
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ResumeConfig {
+    // Seconds to keep the server running after the client disconnected.
+    pub grace_period_secs: u64,
+    // Maximum number of messages from the server to buffer while disconnected.
+    // The session is terminated when exceeded.
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+}
+
+fn default_buffer_size() -> usize {
+    1000
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct QueueConfig {
+    // Maximum number of connections waiting. Rejected with 503 when full.
+    pub size: usize,
+    // Seconds to wait before giving up.
+    pub timeout_secs: u64,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    // Tokens allowing everything. Each can be `file:<path>` to read from the file.
+    #[serde(default)]
+    pub tokens: Vec<Secret>,
+    // Secret to verify expiring tokens signed with HMAC-SHA256.
+    // Claims in the token can limit the servers and the workspace.
+    // Can be `file:<path>` to read from the file.
+    #[serde(default)]
+    pub hmac_secret: Option<Secret>,
+    // Verify JWTs signed with RS256 or HS256.
+    #[serde(default)]
+    pub jwt: Option<JwtConfig>,
+}
+
+// Claims of JWTs used in addition to the registered claims (`sub`, `exp`, `nbf`, `iss`, `aud`):
+// - `servers`: names of the servers allowed to start
+// - `workspace`: ID of the only workspace allowed to use
+// - `max_session_secs`: maximum duration of the session
+// - `read_only`: don't write files on `didSave` and through `/files`
+// - `sql_drivers`: sqls `init` drivers allowed
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    // Path to the JWKS file with the keys to verify with.
+    // Keys of type `RSA` are used for RS256, and `oct` for HS256.
+    pub jwks_path: String,
+    // Require `iss` to be this if present.
+    #[serde(default)]
+    pub issuer: Option<String>,
+    // Require `aud` to include this if present.
+    #[serde(default)]
+    pub audience: Option<String>,
+    // Seconds of clock skew allowed when checking `exp` and `nbf`.
+    #[serde(default = "default_leeway_secs")]
+    pub leeway_secs: u64,
+}
+
+fn default_leeway_secs() -> u64 {
+    60
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    // Origins like `https://example.com`. `https://*.example.com` allows any subdomain
+    // of `example.com`, and `*` allows any origin.
+    // Requests with `Origin` not in the list are rejected with 403.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    // Methods allowed in preflight requests.
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    // Headers allowed in preflight requests.
+    #[serde(default = "default_cors_headers")]
+    pub allowed_headers: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: default_cors_methods(),
+            allowed_headers: default_cors_headers(),
+        }
+    }
+}
+
+fn default_cors_methods() -> Vec<String> {
+    vec!["GET".to_owned(), "POST".to_owned(), "OPTIONS".to_owned()]
+}
+
+fn default_cors_headers() -> Vec<String> {
+    vec!["content-type".to_owned(), "authorization".to_owned()]
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    // PEM file with the certificate chain.
+    pub cert_path: String,
+    // PEM file with the private key.
+    pub key_path: String,
+    // PEM file with the CA certificates to verify client certificates with.
+    // Clients without a valid certificate are rejected when present.
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    // Seconds between checks for changes of the files. New connections use
+    // the reloaded certificate, established connections are not affected.
+    #[serde(default = "default_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+}
+
+pub fn default_reload_interval_secs() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    // Key is the LSP method like `textDocument/completion`, value is the limit in each session.
+    // `*` limits all the other requests together.
+    // Requests over the limit are answered by the proxy with `ContentModified` error.
+    // Notifications over the limit are dropped, so document synchronization like
+    // `textDocument/didChange` can't be limited.
+    #[serde(default)]
+    pub methods: HashMap<String, RateLimit>,
+    // Limit of requests to `/files` from each IP address.
+    // Requests over the limit are rejected with 429. Requests from unix sockets are not limited.
+    #[serde(default)]
+    pub files: Option<RateLimit>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    // Sustained rate allowed.
+    pub per_sec: f64,
+    // Number allowed at once after being idle. Defaults to `per_sec`, at least 1.
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    // Directory to create workspaces in. Each workspace is named after the session ID.
+    // Defaults to `lsp-ws-proxy` in the temporary directory.
+    #[serde(default)]
+    pub root: Option<String>,
+    // Directory to copy into each new workspace.
+    #[serde(default)]
+    pub template: Option<String>,
+    // Seconds to keep the workspace after the session ended.
+    // Removed immediately when not present.
+    #[serde(default)]
+    pub retain_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct SqlConfig {
     pub host: String,
     pub port: u16,
     pub admin_username: String,
-    pub admin_password: String,
+    // Can be `file:<path>` to read from the file.
+    pub admin_password: Secret,
     pub proto: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let json = "{\n  \"servers\": {\"rust\": {\"command\": [\"rust-analyzer\"]}}\n}";
+        let yaml = "servers:\n  rust:\n    command: [rust-analyzer]\n";
+        let toml = "[servers.rust]\ncommand = [\"rust-analyzer\"]\n";
+        for (text, format) in [
+            (json, Format::Json),
+            (yaml, Format::Yaml),
+            (toml, Format::Toml),
+        ] {
+            let config = parse_config(text, format).unwrap();
+            assert_eq!(config.servers.unwrap()["rust"].command, ["rust-analyzer"]);
+        }
+
+        let location = |text: &str, format| parse_config(text, format).unwrap_err().location;
+        assert_eq!(
+            location("{\n  \"server\": {}\n}", Format::Json),
+            Some((2, 10))
+        );
+        assert_eq!(
+            location("servers:\n  rust:\n    command: []\n", Format::Yaml),
+            Some((3, 5))
+        );
+        assert_eq!(
+            location("[sql.oracle]\nhost = \"localhost\"\n", Format::Toml),
+            Some((1, 6))
+        );
+
+        assert_eq!(Format::from_path("config.yml"), Format::Yaml);
+        assert_eq!(
+            Format::from_path("/etc/lsp-ws-proxy/config.toml"),
+            Format::Toml
+        );
+        assert_eq!(Format::from_path("config.json"), Format::Json);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |rate_limit: serde_json::Value| {
+            serde_json::from_value::<Config>(serde_json::json!({ "rate_limit": rate_limit }))
+                .unwrap()
+        };
+        let limit = serde_json::json!({"per_sec": 1});
+        assert!(
+            config(serde_json::json!({"methods": {"textDocument/hover": limit}}))
+                .validate()
+                .is_ok()
+        );
+        assert!(config(serde_json::json!({"files": {"per_sec": 0}}))
+            .validate()
+            .is_err());
+        let err = config(serde_json::json!({"methods": {"textDocument/didChange": limit}}))
+            .validate()
+            .unwrap_err();
+        assert!(err.starts_with("rate_limit.methods.textDocument/didChange"));
+    }
+
+    #[test]
+    fn test_secrets() {
+        let dir = std::env::temp_dir().join(format!("lsp-ws-proxy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("admin_password");
+        std::fs::write(&secret, "hunter2\n").unwrap();
+        let path = dir.join("config.yaml");
+        let path = path.to_str().unwrap();
+
+        std::fs::write(
+            path,
+            format!(
+                concat!(
+                    "sql:\n",
+                    "  mysql:\n",
+                    "    host: ${{LSP_WS_PROXY_TEST_UNSET:-localhost}}\n",
+                    "    port: 3306\n",
+                    "    admin_username: root\n",
+                    "    admin_password: file:{}\n",
+                    "auth:\n",
+                    "  tokens: [plain]\n",
+                ),
+                secret.display()
+            ),
+        )
+        .unwrap();
+        let config = read_config_from_file(path).unwrap();
+        let sql = &config.sql.as_ref().unwrap()["mysql"];
+        assert_eq!(sql.host, "localhost");
+        assert_eq!(sql.admin_password.expose(), "hunter2");
+        assert_eq!(config.auth.as_ref().unwrap().tokens[0].expose(), "plain");
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("hunter2") && !debug.contains("plain"));
+
+        std::fs::write(
+            path,
+            "servers:\n  rust:\n    command: [${LSP_WS_PROXY_TEST_UNSET}]\n",
+        )
+        .unwrap();
+        let err = read_config_from_file(path).unwrap_err();
+        assert!(err.starts_with(&format!("{}:3:15: ", path)), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
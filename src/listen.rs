@@ -0,0 +1,287 @@
+//! Addresses to accept connections on.
+use std::{convert::Infallible, fmt, future::Future, io, net::SocketAddr, pin::Pin, sync::Arc};
+
+use futures_util::{future::join_all, Stream};
+use hyper::service::{make_service_fn, service_fn, Service};
+use tokio::io::{AsyncRead, AsyncWrite};
+use warp::Filter;
+
+use crate::api::{shutdown::Shutdown, tls::Tls};
+
+/// Where to accept connections.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listen {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listen::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Listen::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Parse `<addr>:<port>`, `<port>` or `unix:<path>`.
+pub fn parse_listen(value: &str) -> Result<Listen, String> {
+    if let Some(path) = value.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return if path.is_empty() {
+            Err(format!("{} is missing the socket path", value))
+        } else {
+            Ok(Listen::Unix(path.into()))
+        };
+        #[cfg(not(unix))]
+        return Err(format!("{} is not supported on this platform", path));
+    }
+
+    // Allow specifying only a port number.
+    if value.chars().all(|c| c.is_ascii_digit()) {
+        return parse_listen(&format!("0.0.0.0:{}", value));
+    }
+
+    match value.parse::<SocketAddr>() {
+        Ok(addr) => Ok(Listen::Tcp(addr)),
+        Err(_) => Err(format!("{} cannot be parsed as SocketAddr", value)),
+    }
+}
+
+/// Parse permissions in octal like `660`.
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("{} is not a valid octal mode", value))
+}
+
+/// The other end of a connection, set as an extension of each request.
+///
+/// `warp::addr::remote` is only known for plain TCP connections bound by `warp`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    Unix,
+}
+
+type Server = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Serve `routes` on each of `listens` until shutdown is requested. Every listener serves the
+/// same routes. TCP connections use TLS if `tls` is set. Unix sockets are created with `socket_mode`.
+pub async fn serve<F>(
+    routes: F,
+    listens: &[Listen],
+    #[cfg_attr(not(unix), allow(unused_variables))] socket_mode: Option<u32>,
+    tls: Option<Arc<Tls>>,
+    shutdown: &Arc<Shutdown>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Filter<Extract = (warp::reply::Response,), Error = warp::Rejection>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    let mut servers: Vec<Server> = Vec::new();
+    for listen in listens {
+        let signal = shutdown.requested();
+        match listen {
+            Listen::Tcp(addr) => match &tls {
+                Some(tls) => {
+                    let listener = tokio::net::TcpListener::bind(addr)
+                        .await
+                        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+                    tracing::info!("listening on {} with TLS", addr);
+                    let incoming = tls.clone().incoming(listener);
+                    servers.push(serve_incoming(
+                        routes.clone(),
+                        incoming,
+                        |stream| match stream.get_ref().0.peer_addr() {
+                            Ok(addr) => Some(Peer::Tcp(addr)),
+                            Err(_) => None,
+                        },
+                        signal,
+                    ));
+                }
+                None => {
+                    let (addr, server) = warp::serve(routes.clone())
+                        .try_bind_with_graceful_shutdown(*addr, signal)
+                        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+                    tracing::info!("listening on {}", addr);
+                    servers.push(Box::pin(server));
+                }
+            },
+            #[cfg(unix)]
+            Listen::Unix(path) => {
+                let listener = unix::bind(path, socket_mode)
+                    .map_err(|e| format!("Failed to bind {}: {}", listen, e))?;
+                tracing::info!("listening on {}", listen);
+                servers.push(serve_incoming(
+                    routes.clone(),
+                    unix::incoming(listener),
+                    |_| Some(Peer::Unix),
+                    signal,
+                ));
+            }
+        }
+    }
+    if let Some(tls) = &tls {
+        tls.watch();
+    }
+
+    join_all(servers).await;
+    #[cfg(unix)]
+    for listen in listens {
+        if let Listen::Unix(path) = listen {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    Ok(())
+}
+
+/// Serve `routes` on connections from `incoming` like `warp` does, with `Peer` from `peer`.
+fn serve_incoming<F, S>(
+    routes: F,
+    incoming: impl Stream<Item = io::Result<S>> + Send + 'static,
+    peer: fn(&S) -> Option<Peer>,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Server
+where
+    F: Filter<Extract = (warp::reply::Response,), Error = warp::Rejection>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |stream: &S| {
+        let peer = peer(stream);
+        let mut service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req: hyper::Request<hyper::Body>| {
+                if let Some(peer) = peer {
+                    req.extensions_mut().insert(peer);
+                }
+                service.call(req)
+            }))
+        }
+    });
+    let server = hyper::Server::builder(hyper::server::accept::from_stream(incoming))
+        .serve(make_service)
+        .with_graceful_shutdown(signal);
+    Box::pin(async move {
+        if let Err(err) = server.await {
+            tracing::error!("server error: {}", err);
+        }
+    })
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::DirBuilder,
+        io,
+        os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        path::Path,
+        time::Duration,
+    };
+
+    use futures_util::{stream, Stream};
+    use tokio::net::{UnixListener, UnixStream};
+
+    /// Bind to `path`, replacing a socket left from a previous run.
+    pub fn bind(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let mode = match mode {
+            Some(mode) => mode,
+            None => return UnixListener::bind(path),
+        };
+
+        // Bind in a directory only we can access, so that the socket can't be connected to
+        // before it has `mode`. Changing the umask instead would affect other threads.
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing file name"))?;
+        let mut private = path.to_owned();
+        private.set_file_name(format!(
+            ".{}.{}",
+            file_name.to_string_lossy(),
+            std::process::id()
+        ));
+        DirBuilder::new().mode(0o700).create(&private)?;
+        let bind_in = |private: &Path| {
+            let socket = private.join("socket");
+            let listener = UnixListener::bind(&socket)?;
+            std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&socket, path)?;
+            Ok(listener)
+        };
+        let result = bind_in(&private);
+        let _ = std::fs::remove_dir_all(&private);
+        result
+    }
+
+    pub fn incoming(listener: UnixListener) -> impl Stream<Item = io::Result<UnixStream>> + Send {
+        stream::unfold(listener, |listener| async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => return Some((Ok(stream), listener)),
+                    Err(err) => {
+                        tracing::warn!("failed to accept connection: {}", err);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen() {
+        assert_eq!(
+            parse_listen("9999"),
+            Ok(Listen::Tcp("0.0.0.0:9999".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_listen("127.0.0.1:8888"),
+            Ok(Listen::Tcp("127.0.0.1:8888".parse().unwrap()))
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            parse_listen("unix:/run/lsp-ws-proxy.sock"),
+            Ok(Listen::Unix("/run/lsp-ws-proxy.sock".into()))
+        );
+        assert!(parse_listen("unix:").is_err());
+        assert!(parse_listen("localhost").is_err());
+
+        assert_eq!(parse_mode("660"), Ok(0o660));
+        assert_eq!(parse_mode("0600"), Ok(0o600));
+        assert!(parse_mode("999").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bind_unix_with_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("lsp-ws-proxy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("proxy.sock");
+        let _listener = unix::bind(&path, Some(0o660)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        // Only the socket is left.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
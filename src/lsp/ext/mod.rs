@@ -1,6 +1,10 @@
 //! Nonstandard LSP features.
+pub mod proxy;
 mod relative_uri;
 mod sqls;
 
 pub use relative_uri::remap_relative_uri;
-pub use sqls::create_database_on_init;
+pub use sqls::{
+    check_admin_connection as sqls_check_admin_connection, create_database_on_init,
+    init_driver as sqls_init_driver, SqlsDatabase,
+};
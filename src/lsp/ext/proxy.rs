@@ -0,0 +1,32 @@
+//! Notifications sent by the proxy itself.
+use serde::{Deserialize, Serialize};
+
+/// Params for `proxy/serverRestarted` sent to the client after the server
+/// exited unexpectedly and was restarted.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerRestartedParams {
+    /// Number of restarts so far in this session.
+    pub restarts: u32,
+    /// Maximum number of restarts allowed.
+    pub max_restarts: u32,
+}
+
+/// Params for `proxy/queue` sent to the client waiting for the server to start
+/// when the limits are reached.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct QueueParams {
+    /// Position in the queue, starting from 1.
+    pub position: usize,
+}
+
+/// Params for `proxy/session` sent to the client after `initialize`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SessionParams {
+    /// ID of the session. Used with `POST /files?session=<id>` to access the workspace,
+    /// and to notify the server of the changes.
+    pub id: String,
+    /// The client can reconnect with `?session=<token>` to resume the session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
@@ -2,7 +2,10 @@ use std::collections::HashMap;
 
 use url::Url;
 
-use crate::lsp::{Message, Notification, Request, Response, ResponseResult};
+use crate::lsp::{
+    response::{InlayHint, InlayHintLabel},
+    Message, Notification, Request, Response, ResponseResult,
+};
 
 /// Remap URI relative to current directory (`source://`) to absolute URI (`file://`).  
 /// `source://` was chosen because it's used by [Metals Remote Language Server].
@@ -61,6 +64,18 @@ fn remap_notification(notification: &mut Notification, cwd: &Url) -> Result<(),
             remap_text_document_identifier(&mut p.text_document, cwd)?;
         }
 
+        Notification::DidCreateFiles { params: p } => {
+            remap_create_files(p, cwd)?;
+        }
+
+        Notification::DidRenameFiles { params: p } => {
+            remap_rename_files(p, cwd)?;
+        }
+
+        Notification::DidDeleteFiles { params: p } => {
+            remap_delete_files(p, cwd)?;
+        }
+
         Notification::PublishDiagnostics { params: p } => {
             // `to_source` because this goes to client
             if let Some(uri) = to_source(&p.uri, cwd)? {
@@ -75,7 +90,10 @@ fn remap_notification(notification: &mut Notification, cwd: &Url) -> Result<(),
         | Notification::ShowMessage { params: _ }
         | Notification::Progress { params: _ }
         | Notification::CancelRequest { params: _ }
-        | Notification::TelemetryEvent { params: _ } => {}
+        | Notification::TelemetryEvent { params: _ }
+        | Notification::ServerRestarted { params: _ }
+        | Notification::Session { params: _ }
+        | Notification::Queue { params: _ } => {}
     }
 
     Ok(())
@@ -199,6 +217,96 @@ fn remap_request(request: &mut Request, cwd: &Url) -> Result<(), std::io::Error>
             remap_text_document_identifier(&mut p.text_document, cwd)?;
         }
 
+        Request::LinkedEditingRange { id: _, params: p } => {
+            remap_text_document_identifier(
+                &mut p.text_document_position_params.text_document,
+                cwd,
+            )?;
+        }
+
+        Request::Moniker { id: _, params: p } => {
+            remap_text_document_identifier(
+                &mut p.text_document_position_params.text_document,
+                cwd,
+            )?;
+        }
+
+        Request::SemanticTokensFull { id: _, params: p } => {
+            remap_text_document_identifier(&mut p.text_document, cwd)?;
+        }
+
+        Request::SemanticTokensFullDelta { id: _, params: p } => {
+            remap_text_document_identifier(&mut p.text_document, cwd)?;
+        }
+
+        Request::SemanticTokensRange { id: _, params: p } => {
+            remap_text_document_identifier(&mut p.text_document, cwd)?;
+        }
+
+        Request::CallHierarchyPrepare { id: _, params: p } => {
+            remap_text_document_identifier(
+                &mut p.text_document_position_params.text_document,
+                cwd,
+            )?;
+        }
+
+        Request::CallHierarchyIncomingCalls { id: _, params: p } => {
+            remap_hierarchy_item(&mut p.item, to_file, cwd)?;
+        }
+
+        Request::CallHierarchyOutgoingCalls { id: _, params: p } => {
+            remap_hierarchy_item(&mut p.item, to_file, cwd)?;
+        }
+
+        Request::TypeHierarchyPrepare { id: _, params: p } => {
+            remap_text_document_identifier(
+                &mut p.text_document_position_params.text_document,
+                cwd,
+            )?;
+        }
+
+        Request::TypeHierarchySupertypes { id: _, params: p }
+        | Request::TypeHierarchySubtypes { id: _, params: p } => {
+            remap_hierarchy_item(&mut p.item, to_file, cwd)?;
+        }
+
+        Request::InlayHint { id: _, params: p } => {
+            remap_text_document_identifier(&mut p.text_document, cwd)?;
+        }
+
+        Request::InlayHintResolve { id: _, params: p } => {
+            remap_inlay_hint(p, to_file, cwd)?;
+        }
+
+        Request::InlineValue { id: _, params: p } => {
+            remap_text_document_identifier(&mut p.text_document, cwd)?;
+        }
+
+        Request::DocumentDiagnostic { id: _, params: p } => {
+            remap_text_document_identifier(&mut p.text_document, cwd)?;
+        }
+
+        Request::WorkspaceDiagnostic { id: _, params: p } => {
+            for previous in &mut p.previous_result_ids {
+                if let Some(uri) = to_file(&previous.uri, cwd)? {
+                    previous.uri = uri;
+                }
+            }
+        }
+
+        Request::WillCreateFiles { id: _, params: p } => {
+            remap_create_files(p, cwd)?;
+        }
+
+        // The `WorkspaceEdit` in the response is remapped like other edits.
+        Request::WillRenameFiles { id: _, params: p } => {
+            remap_rename_files(p, cwd)?;
+        }
+
+        Request::WillDeleteFiles { id: _, params: p } => {
+            remap_delete_files(p, cwd)?;
+        }
+
         // To Client
         Request::ApplyEdit { id: _, params: p } => {
             remap_workspace_edit(&mut p.edit, cwd)?;
@@ -223,6 +331,7 @@ fn remap_request(request: &mut Request, cwd: &Url) -> Result<(), std::io::Error>
         | Request::UnregisterCapability { id: _, params: _ }
         | Request::CreateWorkDoneProgress { id: _, params: _ }
         | Request::CancelWorkDoneProgress { id: _, params: _ }
+        | Request::WorkspaceDiagnosticRefresh { id: _, params: _ }
         | Request::Symbol { id: _, params: _ }
         | Request::ExecuteCommand { id: _, params: _ }
         | Request::Shutdown { id: _, params: _ } => {}
@@ -286,6 +395,34 @@ fn remap_response(response: &mut Response, cwd: &Url) -> Result<(), std::io::Err
                     }
                 }
 
+                ResponseResult::HierarchyItems(items) => {
+                    for item in items {
+                        remap_hierarchy_item(item, to_source, cwd)?;
+                    }
+                }
+
+                ResponseResult::CallHierarchyIncomingCalls(calls) => {
+                    for call in calls {
+                        remap_hierarchy_item(&mut call.from, to_source, cwd)?;
+                    }
+                }
+
+                ResponseResult::CallHierarchyOutgoingCalls(calls) => {
+                    for call in calls {
+                        remap_hierarchy_item(&mut call.to, to_source, cwd)?;
+                    }
+                }
+
+                ResponseResult::InlayHints(hints) => {
+                    for hint in hints {
+                        remap_inlay_hint(hint, to_source, cwd)?;
+                    }
+                }
+
+                ResponseResult::InlayHintResolve(hint) => {
+                    remap_inlay_hint(hint, to_source, cwd)?;
+                }
+
                 ResponseResult::WorkspaceFolders(folders) => {
                     for folder in folders {
                         // `to_file` because this is a response from Client.
@@ -296,18 +433,44 @@ fn remap_response(response: &mut Response, cwd: &Url) -> Result<(), std::io::Err
                 }
 
                 ResponseResult::WorkspaceEditWithBoth(edit) => {
-                    remap_workspace_edit_changes(&mut edit.changes, cwd)?;
+                    remap_uri_keys(&mut edit.changes, cwd)?;
                     remap_document_changes(&mut edit.document_changes, cwd)?;
                 }
 
                 ResponseResult::WorkspaceEditWithChanges(edit) => {
-                    remap_workspace_edit_changes(&mut edit.changes, cwd)?;
+                    remap_uri_keys(&mut edit.changes, cwd)?;
                 }
 
                 ResponseResult::WorkspaceEditWithDocumentChanges(edit) => {
                     remap_document_changes(&mut edit.document_changes, cwd)?;
                 }
 
+                ResponseResult::DocumentDiagnostic(report) => {
+                    let related_documents = match report {
+                        lsp_types::DocumentDiagnosticReport::Full(r) => &mut r.related_documents,
+                        lsp_types::DocumentDiagnosticReport::Unchanged(r) => {
+                            &mut r.related_documents
+                        }
+                    };
+                    if let Some(related_documents) = related_documents {
+                        remap_uri_keys(related_documents, cwd)?;
+                    }
+                }
+
+                ResponseResult::WorkspaceDiagnostic(report) => {
+                    for item in &mut report.items {
+                        let uri = match item {
+                            lsp_types::WorkspaceDocumentDiagnosticReport::Full(r) => &mut r.uri,
+                            lsp_types::WorkspaceDocumentDiagnosticReport::Unchanged(r) => {
+                                &mut r.uri
+                            }
+                        };
+                        if let Some(source) = to_source(uri, cwd)? {
+                            *uri = source;
+                        }
+                    }
+                }
+
                 ResponseResult::Any(_) => {}
             }
         }
@@ -351,7 +514,7 @@ fn remap_workspace_edit(
     cwd: &Url,
 ) -> Result<(), std::io::Error> {
     if let Some(changes) = &mut workspace_edit.changes {
-        remap_workspace_edit_changes(changes, cwd)?;
+        remap_uri_keys(changes, cwd)?;
     }
 
     if let Some(doc_changes) = &mut workspace_edit.document_changes {
@@ -360,11 +523,8 @@ fn remap_workspace_edit(
     Ok(())
 }
 
-/// Remap keys of `WorkspaceEdit.changes`
-fn remap_workspace_edit_changes(
-    changes: &mut HashMap<Url, Vec<lsp_types::TextEdit>>,
-    cwd: &Url,
-) -> Result<(), std::io::Error> {
+/// Remap keys of `WorkspaceEdit.changes` and `relatedDocuments` to use `source://`
+fn remap_uri_keys<T>(changes: &mut HashMap<Url, T>, cwd: &Url) -> Result<(), std::io::Error> {
     let mut tmp = Vec::with_capacity(changes.len());
     for (key, val) in changes.drain() {
         if let Some(rel) = to_source(&key, cwd)? {
@@ -448,6 +608,79 @@ fn remap_text_document_identifier(
     Ok(())
 }
 
+/// Remap `CallHierarchyItem.uri` (and `TypeHierarchyItem.uri`) with `remap`.
+/// The items are sent in both directions.
+fn remap_hierarchy_item(
+    item: &mut lsp_types::CallHierarchyItem,
+    remap: fn(&Url, &Url) -> Result<Option<Url>, std::io::Error>,
+    cwd: &Url,
+) -> Result<(), std::io::Error> {
+    if let Some(uri) = remap(&item.uri, cwd)? {
+        item.uri = uri;
+    }
+    Ok(())
+}
+
+/// Remap `location.uri` of `InlayHint` label parts with `remap`.
+/// The hints are sent in both directions.
+fn remap_inlay_hint(
+    hint: &mut InlayHint,
+    remap: fn(&Url, &Url) -> Result<Option<Url>, std::io::Error>,
+    cwd: &Url,
+) -> Result<(), std::io::Error> {
+    if let InlayHintLabel::LabelParts(parts) = &mut hint.label {
+        for location in parts.iter_mut().filter_map(|part| part.location.as_mut()) {
+            if let Some(uri) = remap(&location.uri, cwd)? {
+                location.uri = uri;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remap `FileCreate.uri` to use `file://`
+fn remap_create_files(
+    params: &mut lsp_types::CreateFilesParams,
+    cwd: &Url,
+) -> Result<(), std::io::Error> {
+    for file in &mut params.files {
+        remap_file_operation_uri(&mut file.uri, cwd)?;
+    }
+    Ok(())
+}
+
+/// Remap `FileRename.oldUri` and `FileRename.newUri` to use `file://`
+fn remap_rename_files(
+    params: &mut lsp_types::RenameFilesParams,
+    cwd: &Url,
+) -> Result<(), std::io::Error> {
+    for file in &mut params.files {
+        remap_file_operation_uri(&mut file.old_uri, cwd)?;
+        remap_file_operation_uri(&mut file.new_uri, cwd)?;
+    }
+    Ok(())
+}
+
+/// Remap `FileDelete.uri` to use `file://`
+fn remap_delete_files(
+    params: &mut lsp_types::DeleteFilesParams,
+    cwd: &Url,
+) -> Result<(), std::io::Error> {
+    for file in &mut params.files {
+        remap_file_operation_uri(&mut file.uri, cwd)?;
+    }
+    Ok(())
+}
+
+/// File operations use `String` for URIs instead of `Url`.
+fn remap_file_operation_uri(uri: &mut String, cwd: &Url) -> Result<(), std::io::Error> {
+    let parsed = Url::parse(uri).map_err(map_parse_error)?;
+    if let Some(remapped) = to_file(&parsed, cwd)? {
+        *uri = remapped.into();
+    }
+    Ok(())
+}
+
 fn remap_workspace_folder(
     folder: &mut lsp_types::WorkspaceFolder,
     cwd: &Url,
@@ -472,6 +705,170 @@ mod tests {
         assert_eq!(remapped.as_str(), "file:///workspace/src/main.rs");
     }
 
+    #[test]
+    fn test_remap_hierarchy() {
+        let cwd = Url::from_directory_path(Path::new("/workspace")).unwrap();
+        let item = serde_json::json!({
+            "name": "main",
+            "kind": 12,
+            "tags": [1],
+            "uri": "source://src/main.rs",
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 2, "character": 1}},
+            "selectionRange": {"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 7}},
+        });
+
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "typeHierarchy/supertypes",
+            "params": {"item": item},
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        assert_eq!(
+            msg["params"]["item"]["uri"],
+            "file:///workspace/src/main.rs"
+        );
+        assert_eq!(msg["params"]["item"]["tags"], serde_json::json!([1]));
+
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": [{"from": msg["params"]["item"], "fromRanges": []}],
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        assert_eq!(msg["result"][0]["from"]["uri"], "source://src/main.rs");
+        assert_eq!(msg["result"][0]["from"]["name"], "main");
+    }
+
+    #[test]
+    fn test_remap_inlay_hints() {
+        let cwd = Url::from_directory_path(Path::new("/workspace")).unwrap();
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": [{
+                "position": {"line": 1, "character": 8},
+                "label": [{
+                    "value": "Foo",
+                    "location": {
+                        "uri": "file:///workspace/src/foo.rs",
+                        "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 3}},
+                    },
+                }],
+                "kind": 1,
+                "paddingLeft": true,
+            }],
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        let hint = &msg["result"][0];
+        assert_eq!(hint["label"][0]["location"]["uri"], "source://src/foo.rs");
+        assert_eq!(hint["kind"], 1);
+        assert_eq!(hint["paddingLeft"], true);
+    }
+
+    #[test]
+    fn test_remap_diagnostic_reports() {
+        let cwd = Url::from_directory_path(Path::new("/workspace")).unwrap();
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {
+                "kind": "full",
+                "items": [],
+                "relatedDocuments": {
+                    "file:///workspace/src/lib.rs": {"kind": "unchanged", "resultId": "1"},
+                },
+            },
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        assert_eq!(
+            msg["result"]["relatedDocuments"]["source://src/lib.rs"]["resultId"],
+            "1"
+        );
+
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {
+                "items": [
+                    {"kind": "full", "uri": "file:///workspace/src/main.rs", "version": 1, "items": []},
+                    {"kind": "unchanged", "uri": "file:///workspace/src/lib.rs", "version": null, "resultId": "1"},
+                ],
+            },
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        assert_eq!(msg["result"]["items"][0]["uri"], "source://src/main.rs");
+        assert_eq!(msg["result"]["items"][1]["uri"], "source://src/lib.rs");
+
+        // Completion lists without items are not workspace reports.
+        let result = serde_json::json!({"isIncomplete": true, "items": []});
+        let msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": result,
+        }))
+        .unwrap();
+        assert_eq!(serde_json::to_value(&msg).unwrap()["result"], result);
+    }
+
+    #[test]
+    fn test_remap_file_operations() {
+        let cwd = Url::from_directory_path(Path::new("/workspace")).unwrap();
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "workspace/willRenameFiles",
+            "params": {"files": [{"oldUri": "source://src/a.rs", "newUri": "source://src/b.rs"}]},
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        assert_eq!(
+            msg["params"]["files"][0]["oldUri"],
+            "file:///workspace/src/a.rs"
+        );
+        assert_eq!(
+            msg["params"]["files"][0]["newUri"],
+            "file:///workspace/src/b.rs"
+        );
+
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/didDeleteFiles",
+            "params": {"files": [{"uri": "source://src/a.rs"}]},
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        assert_eq!(
+            msg["params"]["files"][0]["uri"],
+            "file:///workspace/src/a.rs"
+        );
+
+        let mut msg: Message = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {"changes": {"file:///workspace/src/main.rs": []}},
+        }))
+        .unwrap();
+        remap_relative_uri(&mut msg, &cwd).unwrap();
+        let msg = serde_json::to_value(&msg).unwrap();
+        assert_eq!(
+            msg["result"]["changes"]["source://src/main.rs"],
+            serde_json::json!([])
+        );
+    }
+
     #[test]
     fn test_to_source() {
         let cwd = Url::from_directory_path(Path::new("/workspace")).unwrap();
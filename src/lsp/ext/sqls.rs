@@ -1,5 +1,5 @@
 use crate::{
-    config::Config,
+    config::{Config, SqlConfig},
     lsp::{Message, Request},
 };
 
@@ -63,11 +63,14 @@ impl SqlsDatabase {
         let user_name = format!("lsp_user_{}", &self.id[..8]);
         let password = format!("lsp_pass_{}", &self.id[..8]);
 
-        let admin_url = format!(
-            "mysql://{}:{}@{}:{}/mysql",
-            self.admin_username, self.admin_password, self.host, self.port
+        let admin_options = mysql_options(
+            &self.host,
+            self.port,
+            &self.admin_username,
+            &self.admin_password,
+            "mysql",
         );
-        let admin_pool = MySqlPool::connect(&admin_url).await?;
+        let admin_pool = MySqlPool::connect_with(admin_options).await?;
         let mut tx = admin_pool.begin().await?;
         match self
             .create_mysql_resources(&mut tx, &db_name, &user_name, &password)
@@ -83,12 +86,10 @@ impl SqlsDatabase {
                 self.created_password = Some(password.clone());
 
                 // 连接到新创建的数据库执行初始化SQL
-                let user_url = format!(
-                    "mysql://{}:{}@{}:{}/{}",
-                    user_name, password, self.host, self.port, db_name
-                );
+                let user_options =
+                    mysql_options(&self.host, self.port, &user_name, &password, &db_name);
 
-                let user_pool = MySqlPool::connect(&user_url).await?;
+                let user_pool = MySqlPool::connect_with(user_options).await?;
                 let mut user_tx = user_pool.begin().await?;
 
                 match sqlx::query(init_sql).execute(&mut *user_tx).await {
@@ -146,12 +147,15 @@ impl SqlsDatabase {
     async fn cleanup_mysql_resources(
         &mut self,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let admin_url = format!(
-            "mysql://{}:{}@{}:{}/mysql",
-            self.admin_username, self.admin_password, self.host, self.port
+        let admin_options = mysql_options(
+            &self.host,
+            self.port,
+            &self.admin_username,
+            &self.admin_password,
+            "mysql",
         );
 
-        let admin_pool = sqlx::MySqlPool::connect(&admin_url).await?;
+        let admin_pool = sqlx::MySqlPool::connect_with(admin_options).await?;
 
         if let Some(db_name) = &self.created_database {
             let drop_db_sql = format!("DROP DATABASE IF EXISTS `{}`", db_name);
@@ -179,12 +183,15 @@ impl SqlsDatabase {
         let password = format!("lsp_pass_{}", &self.id[..8]);
 
         // 连接到PostgreSQL服务器
-        let admin_url = format!(
-            "postgres://{}:{}@{}:{}/postgres",
-            self.admin_username, self.admin_password, self.host, self.port
+        let admin_options = postgres_options(
+            &self.host,
+            self.port,
+            &self.admin_username,
+            &self.admin_password,
+            "postgres",
         );
 
-        let admin_pool = PgPool::connect(&admin_url).await?;
+        let admin_pool = PgPool::connect_with(admin_options).await?;
 
         // PostgreSQL不支持DDL事务，需要手动管理回滚
         match self
@@ -197,12 +204,10 @@ impl SqlsDatabase {
                 self.created_password = Some(password.clone());
 
                 // 连接到新数据库执行初始化SQL
-                let user_url = format!(
-                    "postgres://{}:{}@{}:{}/{}",
-                    user_name, password, self.host, self.port, db_name
-                );
+                let user_options =
+                    postgres_options(&self.host, self.port, &user_name, &password, &db_name);
 
-                let user_pool = PgPool::connect(&user_url).await?;
+                let user_pool = PgPool::connect_with(user_options).await?;
                 let mut tx = user_pool.begin().await?;
 
                 match sqlx::query(init_sql).execute(&mut *tx).await {
@@ -244,12 +249,15 @@ impl SqlsDatabase {
     async fn cleanup_postgres_resources(
         &mut self,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let admin_url = format!(
-            "postgres://{}:{}@{}:{}/postgres",
-            self.admin_username, self.admin_password, self.host, self.port
+        let admin_options = postgres_options(
+            &self.host,
+            self.port,
+            &self.admin_username,
+            &self.admin_password,
+            "postgres",
         );
 
-        let admin_pool = sqlx::PgPool::connect(&admin_url).await?;
+        let admin_pool = sqlx::PgPool::connect_with(admin_options).await?;
 
         if let Some(db_name) = &self.created_database {
             // 断开数据库连接
@@ -324,6 +332,92 @@ impl SqlsDatabase {
     }
 }
 
+/// Connect to the database server of `driver` with the admin credentials in `config`.
+pub async fn check_admin_connection(
+    driver: &str,
+    config: &SqlConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use sqlx::Connection;
+
+    match driver {
+        "mysql" => {
+            let admin_options = mysql_options(
+                &config.host,
+                config.port,
+                &config.admin_username,
+                config.admin_password.expose(),
+                "mysql",
+            );
+            sqlx::MySqlConnection::connect_with(&admin_options)
+                .await?
+                .close()
+                .await?;
+        }
+        "postgres" => {
+            let admin_options = postgres_options(
+                &config.host,
+                config.port,
+                &config.admin_username,
+                config.admin_password.expose(),
+                "postgres",
+            );
+            sqlx::PgConnection::connect_with(&admin_options)
+                .await?
+                .close()
+                .await?;
+        }
+        // SQLite databases are files created for each session.
+        "sqlite" => {}
+        _ => return Err(format!("Unsupported driver: {}", driver).into()),
+    }
+    Ok(())
+}
+
+/// Options to connect to MySQL. Unlike a URL, the credentials don't need to be escaped.
+fn mysql_options(
+    host: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+    database: &str,
+) -> sqlx::mysql::MySqlConnectOptions {
+    sqlx::mysql::MySqlConnectOptions::new()
+        .host(host)
+        .port(port)
+        .username(username)
+        .password(password)
+        .database(database)
+}
+
+/// Options to connect to PostgreSQL. Unlike a URL, the credentials don't need to be escaped.
+fn postgres_options(
+    host: &str,
+    port: u16,
+    username: &str,
+    password: &str,
+    database: &str,
+) -> sqlx::postgres::PgConnectOptions {
+    sqlx::postgres::PgConnectOptions::new()
+        .host(host)
+        .port(port)
+        .username(username)
+        .password(password)
+        .database(database)
+}
+
+/// Driver of the database to create with `init` if `msg` is `initialize` with it.
+pub fn init_driver(msg: &Message) -> Option<&str> {
+    match msg {
+        Message::Request(Request::Initialize { params, .. }) => params
+            .initialization_options
+            .as_ref()?
+            .get("init")?
+            .get("driver")?
+            .as_str(),
+        _ => None,
+    }
+}
+
 /// Extract the database name from the message
 /// ```json
 /// {
@@ -375,7 +469,7 @@ pub async fn create_database_on_init(
                         let mut db = SqlsDatabase::new(
                             driver.to_string(),
                             sql_config.admin_username.clone(),
-                            sql_config.admin_password.clone(),
+                            sql_config.admin_password.expose().to_owned(),
                             sql_config.host.clone(),
                             sql_config.port,
                         );
@@ -421,6 +515,20 @@ mod tests {
     use lsp_types::{ClientCapabilities, InitializeParams};
     use serde_json::json;
 
+    #[test]
+    fn test_connect_options() {
+        // Would be read as the user `admin` at the host `ss` from a URL.
+        let password = "p@ss:w/rd#%";
+        let mysql = mysql_options("db.internal", 3306, "admin", password, "mysql");
+        assert_eq!(mysql.get_host(), "db.internal");
+        assert_eq!(mysql.get_username(), "admin");
+        assert_eq!(mysql.get_database(), Some("mysql"));
+        let postgres = postgres_options("db.internal", 5432, "admin", password, "postgres");
+        assert_eq!(postgres.get_host(), "db.internal");
+        assert_eq!(postgres.get_port(), 5432);
+        assert_eq!(postgres.get_database(), Some("postgres"));
+    }
+
     #[tokio::test]
     async fn test_mysql_init() {
         let init_options = json!({
@@ -467,12 +575,22 @@ mod tests {
                         host: "127.0.0.1".to_string(),
                         port: 3306,
                         admin_username: "root".to_string(),
-                        admin_password: "root".to_string(),
+                        admin_password: crate::config::Secret::new("root"),
                         proto: Some("tcp".to_string()),
                     },
                 )]
                 .into(),
             ),
+            resume: None,
+            workspace: None,
+            max_connections: None,
+            queue: None,
+            idle_timeout_secs: None,
+            shutdown_timeout_secs: None,
+            auth: None,
+            cors: None,
+            tls: None,
+            rate_limit: None,
         };
 
         let data_base = create_database_on_init(&mut message, "sql", Some(&config))
@@ -14,7 +14,7 @@ use serde::{Deserialize, Serialize};
 pub use notification::Notification;
 pub use request::Request;
 pub use response::{Response, ResponseResult};
-use types::Unknown;
+use types::{Id, Unknown};
 
 #[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(untagged)]
@@ -29,6 +29,36 @@ pub enum Message {
     Unknown(Unknown),
 }
 
+impl Message {
+    /// Returns the ID if this is a request, including unknown ones.
+    pub fn request_id(&self) -> Option<Id> {
+        match self {
+            Self::Request(request) => Some(request.id().clone()),
+            Self::Unknown(unknown) => unknown.request_id(),
+            Self::Notification(_) | Self::Response(_) => None,
+        }
+    }
+
+    /// Returns the method if this is a request or a notification, including unknown ones.
+    pub fn method(&self) -> Option<&str> {
+        match self {
+            Self::Request(request) => Some(request.method()),
+            Self::Notification(notification) => Some(notification.method()),
+            Self::Unknown(unknown) => unknown.method(),
+            Self::Response(_) => None,
+        }
+    }
+
+    /// Returns the ID if this is a response.
+    pub fn response_id(&self) -> Option<&Id> {
+        match self {
+            Self::Response(Response::Success { id, .. }) => Some(id),
+            Self::Response(Response::Failure { id, .. }) => id.as_ref(),
+            Self::Request(_) | Self::Notification(_) | Self::Unknown(_) => None,
+        }
+    }
+}
+
 impl From<Request> for Message {
     fn from(request: Request) -> Self {
         Self::Request(request)
@@ -69,6 +99,27 @@ impl TryFrom<serde_json::Value> for Message {
     }
 }
 
+/// A single message, or a [batch] of messages.
+///
+/// [batch]: https://www.jsonrpc.org/specification#batch
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+pub enum Frame {
+    // Must be tried first because `Message::Unknown` accepts arrays.
+    Batch(Vec<Message>),
+
+    Single(Message),
+}
+
+impl FromStr for Frame {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
 // We assume that all messages have `jsonrpc: "2.0"`.
 impl Serialize for Message {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
@@ -157,4 +208,44 @@ mod tests {
         let from_value: Message = serde_json::from_value(v).unwrap();
         assert_eq!(serde_json::to_string(&from_value).unwrap(), s);
     }
+
+    #[test]
+    fn test_method() {
+        let request: Message = serde_json::from_value(json!({
+            "jsonrpc":"2.0","method":"textDocument/hover","id":1,
+            "params":{"textDocument":{"uri":"file:///a.rs"},"position":{"line":0,"character":0}}
+        }))
+        .unwrap();
+        assert_eq!(request.method(), Some("textDocument/hover"));
+        let unknown: Message =
+            serde_json::from_value(json!({"jsonrpc":"2.0","method":"language/status","params":{}}))
+                .unwrap();
+        assert_eq!(unknown.method(), Some("language/status"));
+        let response: Message =
+            serde_json::from_value(json!({"jsonrpc":"2.0","result":{},"id":1})).unwrap();
+        assert_eq!(response.method(), None);
+    }
+
+    #[test]
+    fn test_frame() {
+        let hover = json!({
+            "jsonrpc":"2.0","method":"textDocument/hover","id":1,
+            "params":{"textDocument":{"uri":"file:///a.rs"},"position":{"line":0,"character":0}}
+        });
+        let initialized = json!({"jsonrpc":"2.0","method":"initialized","params":{}});
+        let single = Frame::from_str(&hover.to_string()).unwrap();
+        assert_eq!(
+            single,
+            Frame::Single(serde_json::from_value(hover.clone()).unwrap())
+        );
+        let batch = Frame::from_str(&json!([hover, initialized]).to_string()).unwrap();
+        assert_eq!(
+            batch,
+            Frame::Batch(vec![
+                serde_json::from_value(hover).unwrap(),
+                serde_json::from_value(initialized).unwrap(),
+            ])
+        );
+        assert_eq!(Frame::from_str("[]").unwrap(), Frame::Batch(vec![]));
+    }
 }
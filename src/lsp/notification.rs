@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use super::ext::proxy;
+
 // NOTE Not using `lsp_types::lsp_notification!` because rust-analyzer
 // doesn't seem to understand it well at the moment and shows `{unknown}`.
 
@@ -77,6 +79,27 @@ pub enum Notification {
         params: lsp_types::DidCloseTextDocumentParams,
     },
 
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#workspace_didCreateFiles
+    #[serde(rename = "workspace/didCreateFiles")]
+    DidCreateFiles {
+        params: lsp_types::CreateFilesParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#workspace_didRenameFiles
+    #[serde(rename = "workspace/didRenameFiles")]
+    DidRenameFiles {
+        params: lsp_types::RenameFilesParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#workspace_didDeleteFiles
+    #[serde(rename = "workspace/didDeleteFiles")]
+    DidDeleteFiles {
+        params: lsp_types::DeleteFilesParams,
+    },
+
     // To Client
     // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#window_logMessage
     #[serde(rename = "window/logMessage")]
@@ -110,4 +133,51 @@ pub enum Notification {
     // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#cancelRequest
     #[serde(rename = "$/cancelRequest")]
     CancelRequest { params: lsp_types::CancelParams },
+
+    // To Client (nonstandard)
+    // Sent by the proxy after restarting the server that exited unexpectedly.
+    #[serde(rename = "proxy/serverRestarted")]
+    ServerRestarted {
+        params: proxy::ServerRestartedParams,
+    },
+
+    // To Client (nonstandard)
+    // Sent by the proxy after `initialize` with the ID of the session.
+    #[serde(rename = "proxy/session")]
+    Session { params: proxy::SessionParams },
+
+    // To Client (nonstandard)
+    // Sent by the proxy while the connection is waiting in the queue.
+    #[serde(rename = "proxy/queue")]
+    Queue { params: proxy::QueueParams },
+}
+
+impl Notification {
+    /// Returns the method of the notification.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::Initialized { .. } => "initialized",
+            Self::Exit { .. } => "exit",
+            Self::DidChangeWorkspaceFolders { .. } => "workspace/didChangeWorkspaceFolders",
+            Self::DidChangeConfiguration { .. } => "workspace/didChangeConfiguration",
+            Self::DidChangeWatchedFiles { .. } => "workspace/didChangeWatchedFiles",
+            Self::DidOpen { .. } => "textDocument/didOpen",
+            Self::DidChange { .. } => "textDocument/didChange",
+            Self::WillSave { .. } => "textDocument/willSave",
+            Self::DidSave { .. } => "textDocument/didSave",
+            Self::DidClose { .. } => "textDocument/didClose",
+            Self::DidCreateFiles { .. } => "workspace/didCreateFiles",
+            Self::DidRenameFiles { .. } => "workspace/didRenameFiles",
+            Self::DidDeleteFiles { .. } => "workspace/didDeleteFiles",
+            Self::LogMessage { .. } => "window/logMessage",
+            Self::ShowMessage { .. } => "window/showMessage",
+            Self::TelemetryEvent { .. } => "telemetry/event",
+            Self::PublishDiagnostics { .. } => "textDocument/publishDiagnostics",
+            Self::Progress { .. } => "$/progress",
+            Self::CancelRequest { .. } => "$/cancelRequest",
+            Self::ServerRestarted { .. } => "proxy/serverRestarted",
+            Self::Session { .. } => "proxy/session",
+            Self::Queue { .. } => "proxy/queue",
+        }
+    }
 }
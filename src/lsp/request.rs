@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use super::types::Id;
+use super::{response::InlayHint, types::Id};
 
 // NOTE Not using `lsp_types::lsp_request!` because rust-analyzer
 // doesn't seem to understand it well at the moment and shows `{unknown}`.
@@ -252,6 +252,155 @@ pub enum Request {
         params: lsp_types::SelectionRangeParams,
     },
 
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_linkedEditingRange
+    #[serde(rename = "textDocument/linkedEditingRange")]
+    LinkedEditingRange {
+        id: Id,
+        params: lsp_types::LinkedEditingRangeParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_moniker
+    #[serde(rename = "textDocument/moniker")]
+    Moniker {
+        id: Id,
+        params: lsp_types::MonikerParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_semanticTokens
+    #[serde(rename = "textDocument/semanticTokens/full")]
+    SemanticTokensFull {
+        id: Id,
+        params: lsp_types::SemanticTokensParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_semanticTokens
+    #[serde(rename = "textDocument/semanticTokens/full/delta")]
+    SemanticTokensFullDelta {
+        id: Id,
+        params: lsp_types::SemanticTokensDeltaParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_semanticTokens
+    #[serde(rename = "textDocument/semanticTokens/range")]
+    SemanticTokensRange {
+        id: Id,
+        params: lsp_types::SemanticTokensRangeParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_prepareCallHierarchy
+    #[serde(rename = "textDocument/prepareCallHierarchy")]
+    CallHierarchyPrepare {
+        id: Id,
+        params: lsp_types::CallHierarchyPrepareParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#callHierarchy_incomingCalls
+    #[serde(rename = "callHierarchy/incomingCalls")]
+    CallHierarchyIncomingCalls {
+        id: Id,
+        params: lsp_types::CallHierarchyIncomingCallsParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#callHierarchy_outgoingCalls
+    #[serde(rename = "callHierarchy/outgoingCalls")]
+    CallHierarchyOutgoingCalls {
+        id: Id,
+        params: lsp_types::CallHierarchyOutgoingCallsParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_prepareTypeHierarchy
+    #[serde(rename = "textDocument/prepareTypeHierarchy")]
+    TypeHierarchyPrepare {
+        id: Id,
+        params: lsp_types::TypeHierarchyPrepareParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#typeHierarchy_supertypes
+    #[serde(rename = "typeHierarchy/supertypes")]
+    TypeHierarchySupertypes {
+        id: Id,
+        params: TypeHierarchyItemParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#typeHierarchy_subtypes
+    #[serde(rename = "typeHierarchy/subtypes")]
+    TypeHierarchySubtypes {
+        id: Id,
+        params: TypeHierarchyItemParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_inlayHint
+    #[serde(rename = "textDocument/inlayHint")]
+    InlayHint {
+        id: Id,
+        params: lsp_types::InlayHintParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#inlayHint_resolve
+    #[serde(rename = "inlayHint/resolve")]
+    InlayHintResolve { id: Id, params: InlayHint },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_inlineValue
+    #[serde(rename = "textDocument/inlineValue")]
+    InlineValue {
+        id: Id,
+        params: lsp_types::InlineValueParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#textDocument_pullDiagnostics
+    #[serde(rename = "textDocument/diagnostic")]
+    DocumentDiagnostic {
+        id: Id,
+        params: lsp_types::DocumentDiagnosticParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#workspace_diagnostic
+    #[serde(rename = "workspace/diagnostic")]
+    WorkspaceDiagnostic {
+        id: Id,
+        params: lsp_types::WorkspaceDiagnosticParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#workspace_willCreateFiles
+    #[serde(rename = "workspace/willCreateFiles")]
+    WillCreateFiles {
+        id: Id,
+        params: lsp_types::CreateFilesParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#workspace_willRenameFiles
+    #[serde(rename = "workspace/willRenameFiles")]
+    WillRenameFiles {
+        id: Id,
+        params: lsp_types::RenameFilesParams,
+    },
+
+    // To Server
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#workspace_willDeleteFiles
+    #[serde(rename = "workspace/willDeleteFiles")]
+    WillDeleteFiles {
+        id: Id,
+        params: lsp_types::DeleteFilesParams,
+    },
+
     // To Server
     // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#window_workDoneProgress_cancel
     #[serde(rename = "window/workDoneProgress/cancel")]
@@ -312,4 +461,152 @@ pub enum Request {
         id: Id,
         params: lsp_types::WorkDoneProgressCreateParams,
     },
+
+    // To Client
+    // https://microsoft.github.io/language-server-protocol/specifications/specification-current/#diagnostic_refresh
+    #[serde(rename = "workspace/diagnostic/refresh")]
+    WorkspaceDiagnosticRefresh { id: Id, params: () },
+}
+
+impl Request {
+    /// Returns the ID of the request.
+    pub fn id(&self) -> &Id {
+        match self {
+            Self::Initialize { id, .. }
+            | Self::Shutdown { id, .. }
+            | Self::Symbol { id, .. }
+            | Self::ExecuteCommand { id, .. }
+            | Self::WillSaveWaitUntil { id, .. }
+            | Self::Completion { id, .. }
+            | Self::CompletionResolve { id, .. }
+            | Self::Hover { id, .. }
+            | Self::SignatureHelp { id, .. }
+            | Self::GotoDeclaration { id, .. }
+            | Self::GotoDefinition { id, .. }
+            | Self::GotoTypeDefinition { id, .. }
+            | Self::GotoImplementation { id, .. }
+            | Self::References { id, .. }
+            | Self::DocumentHighlight { id, .. }
+            | Self::DocumentSymbol { id, .. }
+            | Self::CodeAction { id, .. }
+            | Self::CodeLens { id, .. }
+            | Self::CodeLensResolve { id, .. }
+            | Self::DocumentLink { id, .. }
+            | Self::DocumentLinkResolve { id, .. }
+            | Self::DocumentColor { id, .. }
+            | Self::ColorPresentation { id, .. }
+            | Self::Formatting { id, .. }
+            | Self::RangeFormatting { id, .. }
+            | Self::OnTypeFormatting { id, .. }
+            | Self::Rename { id, .. }
+            | Self::PrepareRename { id, .. }
+            | Self::FoldingRange { id, .. }
+            | Self::SelectionRange { id, .. }
+            | Self::LinkedEditingRange { id, .. }
+            | Self::Moniker { id, .. }
+            | Self::SemanticTokensFull { id, .. }
+            | Self::SemanticTokensFullDelta { id, .. }
+            | Self::SemanticTokensRange { id, .. }
+            | Self::CallHierarchyPrepare { id, .. }
+            | Self::CallHierarchyIncomingCalls { id, .. }
+            | Self::CallHierarchyOutgoingCalls { id, .. }
+            | Self::TypeHierarchyPrepare { id, .. }
+            | Self::TypeHierarchySupertypes { id, .. }
+            | Self::TypeHierarchySubtypes { id, .. }
+            | Self::InlayHint { id, .. }
+            | Self::InlayHintResolve { id, .. }
+            | Self::InlineValue { id, .. }
+            | Self::DocumentDiagnostic { id, .. }
+            | Self::WorkspaceDiagnostic { id, .. }
+            | Self::WillCreateFiles { id, .. }
+            | Self::WillRenameFiles { id, .. }
+            | Self::WillDeleteFiles { id, .. }
+            | Self::CancelWorkDoneProgress { id, .. }
+            | Self::ShowMessage { id, .. }
+            | Self::RegisterCapability { id, .. }
+            | Self::UnregisterCapability { id, .. }
+            | Self::WorkspaceFolders { id, .. }
+            | Self::Configuration { id, .. }
+            | Self::ApplyEdit { id, .. }
+            | Self::CreateWorkDoneProgress { id, .. }
+            | Self::WorkspaceDiagnosticRefresh { id, .. } => id,
+        }
+    }
+
+    /// Returns the method of the request.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::Initialize { .. } => "initialize",
+            Self::Shutdown { .. } => "shutdown",
+            Self::Symbol { .. } => "workspace/symbol",
+            Self::ExecuteCommand { .. } => "workspace/executeCommand",
+            Self::WillSaveWaitUntil { .. } => "WillSaveWaitUntil",
+            Self::Completion { .. } => "textDocument/completion",
+            Self::CompletionResolve { .. } => "completionItem/resolve",
+            Self::Hover { .. } => "textDocument/hover",
+            Self::SignatureHelp { .. } => "textDocument/signatureHelp",
+            Self::GotoDeclaration { .. } => "textDocument/declaration",
+            Self::GotoDefinition { .. } => "textDocument/definition",
+            Self::GotoTypeDefinition { .. } => "textDocument/typeDefinition",
+            Self::GotoImplementation { .. } => "textDocument/implementation",
+            Self::References { .. } => "textDocument/references",
+            Self::DocumentHighlight { .. } => "textDocument/documentHighlight",
+            Self::DocumentSymbol { .. } => "textDocument/documentSymbol",
+            Self::CodeAction { .. } => "textDocument/codeAction",
+            Self::CodeLens { .. } => "textDocument/codeLens",
+            Self::CodeLensResolve { .. } => "codeLens/resolve",
+            Self::DocumentLink { .. } => "textDocument/documentLink",
+            Self::DocumentLinkResolve { .. } => "documentLink/resolve",
+            Self::DocumentColor { .. } => "textDocument/documentColor",
+            Self::ColorPresentation { .. } => "textDocument/colorPresentation",
+            Self::Formatting { .. } => "textDocument/formatting",
+            Self::RangeFormatting { .. } => "textDocument/rangeFormatting",
+            Self::OnTypeFormatting { .. } => "textDocument/onTypeFormatting",
+            Self::Rename { .. } => "textDocument/rename",
+            Self::PrepareRename { .. } => "textDocument/prepareRename",
+            Self::FoldingRange { .. } => "textDocument/foldingRange",
+            Self::SelectionRange { .. } => "textDocument/selectionRange",
+            Self::LinkedEditingRange { .. } => "textDocument/linkedEditingRange",
+            Self::Moniker { .. } => "textDocument/moniker",
+            Self::SemanticTokensFull { .. } => "textDocument/semanticTokens/full",
+            Self::SemanticTokensFullDelta { .. } => "textDocument/semanticTokens/full/delta",
+            Self::SemanticTokensRange { .. } => "textDocument/semanticTokens/range",
+            Self::CallHierarchyPrepare { .. } => "textDocument/prepareCallHierarchy",
+            Self::CallHierarchyIncomingCalls { .. } => "callHierarchy/incomingCalls",
+            Self::CallHierarchyOutgoingCalls { .. } => "callHierarchy/outgoingCalls",
+            Self::TypeHierarchyPrepare { .. } => "textDocument/prepareTypeHierarchy",
+            Self::TypeHierarchySupertypes { .. } => "typeHierarchy/supertypes",
+            Self::TypeHierarchySubtypes { .. } => "typeHierarchy/subtypes",
+            Self::InlayHint { .. } => "textDocument/inlayHint",
+            Self::InlayHintResolve { .. } => "inlayHint/resolve",
+            Self::InlineValue { .. } => "textDocument/inlineValue",
+            Self::DocumentDiagnostic { .. } => "textDocument/diagnostic",
+            Self::WorkspaceDiagnostic { .. } => "workspace/diagnostic",
+            Self::WillCreateFiles { .. } => "workspace/willCreateFiles",
+            Self::WillRenameFiles { .. } => "workspace/willRenameFiles",
+            Self::WillDeleteFiles { .. } => "workspace/willDeleteFiles",
+            Self::CancelWorkDoneProgress { .. } => "window/workDoneProgress/cancel",
+            Self::ShowMessage { .. } => "window/showMessageRequest",
+            Self::RegisterCapability { .. } => "client/registerCapability",
+            Self::UnregisterCapability { .. } => "client/unregisterCapability",
+            Self::WorkspaceFolders { .. } => "workspace/workspaceFolders",
+            Self::Configuration { .. } => "workspace/configuration",
+            Self::ApplyEdit { .. } => "workspace/applyEdit",
+            Self::CreateWorkDoneProgress { .. } => "window/workDoneProgress/create",
+            Self::WorkspaceDiagnosticRefresh { .. } => "workspace/diagnostic/refresh",
+        }
+    }
+}
+
+// Params of `typeHierarchy/supertypes` and `typeHierarchy/subtypes`.
+// `lsp_types::TypeHierarchyItem` has a single `SymbolTag` in `tags` instead of an array,
+// so `CallHierarchyItem` with the same fields is used for the item instead.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyItemParams {
+    pub item: lsp_types::CallHierarchyItem,
+    #[serde(flatten)]
+    pub work_done_progress_params: lsp_types::WorkDoneProgressParams,
+    #[serde(flatten)]
+    pub partial_result_params: lsp_types::PartialResultParams,
 }
@@ -29,6 +29,16 @@ pub enum ResponseResult {
     // {targetUri,targetRange,targetSelectionRange,originSelectionRange?}[]
     LocationLinks(Vec<lsp_types::LocationLink>),
     // remap uri
+    // {name,kind,uri,range,selectionRange, tags?,detail?,data?}[]
+    // Must be before `Locations`. Also matches `TypeHierarchyItem[]` with the same fields.
+    HierarchyItems(Vec<lsp_types::CallHierarchyItem>),
+    // remap from.uri
+    // {from,fromRanges}[]
+    CallHierarchyIncomingCalls(Vec<lsp_types::CallHierarchyIncomingCall>),
+    // remap to.uri
+    // {to,fromRanges}[]
+    CallHierarchyOutgoingCalls(Vec<lsp_types::CallHierarchyOutgoingCall>),
+    // remap uri
     // {uri,range}[]
     Locations(Vec<lsp_types::Location>),
     // remap uri
@@ -43,6 +53,12 @@ pub enum ResponseResult {
     // remap target
     // {range,target, tooltip?,data?}
     DocumentLinkWithTargetResolve(DocumentLinkWithTarget),
+    // remap location of label parts
+    // {position,label, kind?,textEdits?,tooltip?,paddingLeft?,paddingRight?,data?}[]
+    InlayHints(Vec<InlayHint>),
+    // remap location of label parts
+    // {position,label, kind?,textEdits?,tooltip?,paddingLeft?,paddingRight?,data?}
+    InlayHintResolve(InlayHint),
     // remap if action.edit is present
     // ({title,command, arguments?} | {title, kind?,diagnostics?,edit?,command?,isPreferred?})[]
     CodeAction(lsp_types::CodeActionResponse),
@@ -55,6 +71,12 @@ pub enum ResponseResult {
     // remap documentChanges
     // {documentChanges}
     WorkspaceEditWithDocumentChanges(WorkspaceEditWithDocumentChanges),
+    // remap keys of relatedDocuments
+    // {kind:"full",items, resultId?,relatedDocuments?} | {kind:"unchanged",resultId, relatedDocuments?}
+    DocumentDiagnostic(lsp_types::DocumentDiagnosticReport),
+    // remap uri of items
+    // {items: ({kind:"full",uri,version,items, resultId?} | {kind:"unchanged",uri,version,resultId})[]}
+    WorkspaceDiagnostic(WorkspaceDiagnosticReport),
 
     // noremap
     // {name,kind,range,selectionRange, detail?,tags?,deprecated?,children?}[]
@@ -130,16 +152,24 @@ pub enum ResponseResult {
     // {range, command?,data?}
     // CodeLensResolve(lsp_types::CodeLens),
 
+    // noremap
+    // {resultId?,data} | {resultId?,edits}
+    // SemanticTokens(lsp_types::SemanticTokensFullDeltaResult),
+
+    // noremap
+    // {ranges, wordPattern?}
+    // LinkedEditingRanges(lsp_types::LinkedEditingRanges),
+
+    // noremap
+    // {scheme,identifier,unique, kind?}[]
+    // Monikers(Vec<lsp_types::Moniker>),
+
+    // noremap
+    // ({range,text} | {range, variableName?,caseSensitiveLookup} | {range, expression?})[]
+    // InlineValues(Vec<lsp_types::InlineValue>),
+
     // noremap
     Any(serde_json::Value),
-    // Proposed
-    //   SemanticTokensFull(lsp_types::SemanticTokensResult),
-    //   SemanticTokensFullDelta(lsp_types::SemanticTokensFullDeltaResult),
-    //   SemanticTokensRange(lsp_types::SemanticTokensRangeResult),
-    //
-    //   CallHierarchyPrepare(Vec<lsp_types::CallHierarchyItem>),
-    //   CallHierarchyOutgoingCalls(Vec<lsp_types::CallHierarchyOutgoingCall>),
-    //   CallHierarchyIncomingCalls(Vec<lsp_types::CallHierarchyIncomingCall>),
 }
 
 // Some custom types to make untagged enum work.
@@ -159,6 +189,34 @@ pub struct DocumentLinkWithTarget {
     pub data: Option<serde_json::Value>,
 }
 
+// `lsp_types::InlayHint` doesn't implement `PartialEq`. Only the fields to remap are typed,
+// and the rest are kept as is.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+pub struct InlayHint {
+    pub position: lsp_types::Position,
+    pub label: InlayHintLabel,
+    #[serde(flatten)]
+    pub rest: serde_json::Map<String, serde_json::Value>,
+}
+
+// `lsp_types::InlayHintLabel`
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum InlayHintLabel {
+    String(String),
+    LabelParts(Vec<InlayHintLabelPart>),
+}
+
+// `lsp_types::InlayHintLabelPart`
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+pub struct InlayHintLabelPart {
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<lsp_types::Location>,
+    #[serde(flatten)]
+    pub rest: serde_json::Map<String, serde_json::Value>,
+}
+
 // `WorkspaceEdit` (`{changes?, documentChanges?}`) needs to be remapped. But we can't
 // match it using untagged enum because both fields are optional making it match any objects.
 // We define the following custom types to workaround it.
@@ -187,3 +245,11 @@ pub struct WorkspaceEditWithChanges {
 pub struct WorkspaceEditWithDocumentChanges {
     pub document_changes: lsp_types::DocumentChanges,
 }
+
+// `lsp_types::WorkspaceDiagnosticReport` (`{items}`) would also match completion lists
+// (`{isIncomplete, items}`) with no items, so unknown fields are denied.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceDiagnosticReport {
+    pub items: Vec<lsp_types::WorkspaceDocumentDiagnosticReport>,
+}
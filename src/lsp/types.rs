@@ -3,7 +3,7 @@ use std::fmt::{self, Display, Formatter};
 use serde::{Deserialize, Serialize};
 
 /// Request ID
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Id {
     /// Numeric ID.
@@ -33,3 +33,21 @@ pub enum Params {
 /// Unknown message type.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Unknown(serde_json::Value);
+
+impl Unknown {
+    /// Returns the ID if this looks like a request (has both `method` and `id`).
+    pub fn request_id(&self) -> Option<Id> {
+        if self.0.get("method").is_some() {
+            self.0
+                .get("id")
+                .and_then(|id| serde_json::from_value(id.clone()).ok())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the method if present.
+    pub fn method(&self) -> Option<&str> {
+        self.0.get("method").and_then(serde_json::Value::as_str)
+    }
+}
@@ -1,13 +1,18 @@
-use std::net::SocketAddr;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use argh::FromArgs;
 use url::Url;
-use warp::{http, Filter};
+use warp::Filter;
 
-use crate::config::Config;
+use crate::{
+    config::Config,
+    listen::{parse_listen, parse_mode, Listen},
+};
 
 mod api;
+mod check;
 mod config;
+mod listen;
 mod lsp;
 
 #[derive(FromArgs)]
@@ -26,6 +31,9 @@ Examples:
   lsp-ws-proxy -- typescript-language-server --stdio
   lsp-ws-proxy --listen 8888 -- rust-analyzer
   lsp-ws-proxy --listen 0.0.0.0:8888 -- rust-analyzer
+  lsp-ws-proxy --listen unix:/run/lsp-ws-proxy.sock --socket-mode 660 -- rust-analyzer
+  # Listen on multiple addresses.
+  lsp-ws-proxy --listen 0.0.0.0:8888 --listen 127.0.0.1:8889 -- rust-analyzer
   # Register multiple servers.
   # Choose the server with query parameter `name` when connecting.
   lsp-ws-proxy --listen 9999 --sync --remap \
@@ -34,16 +42,22 @@ Examples:
     -- html-languageserver --stdio
   # Use json config and choose the server with query parameter `name` when connecting.
   lsp-ws-proxy --listen 9999 --sync --remap -c config.json
+  # The config can also be YAML (`.yaml`, `.yml`) or TOML (`.toml`).
+  lsp-ws-proxy --listen 9999 -c config.yaml
+  # Serve `wss://` with the certificate. Changes to the files are picked up.
+  lsp-ws-proxy --tls-cert cert.pem --tls-key key.pem -- rust-analyzer
+  # Check the config without starting the proxy.
+  lsp-ws-proxy -c config.json check
+  # Start the server `rust` and show its capabilities.
+  lsp-ws-proxy -c config.json probe rust
 */
 struct Options {
-    /// address or port to listen on (default: 0.0.0.0:9999)
-    #[argh(
-        option,
-        short = 'l',
-        default = "String::from(\"0.0.0.0:9999\")",
-        from_str_fn(parse_listen)
-    )]
-    listen: String,
+    /// address, port, or `unix:<path>` to listen on, can be repeated (default: 0.0.0.0:9999)
+    #[argh(option, short = 'l', from_str_fn(parse_listen))]
+    listen: Vec<Listen>,
+    /// permissions of unix sockets in octal, e.g. 660
+    #[argh(option, from_str_fn(parse_mode))]
+    socket_mode: Option<u32>,
     /// write text document to disk on save, and enable `/files` endpoint
     #[argh(switch, short = 's')]
     sync: bool,
@@ -56,6 +70,44 @@ struct Options {
     /// path to config file path
     #[argh(option, short = 'c')]
     config: Option<String>,
+    /// show JSON Schema of the config and exit
+    #[argh(switch)]
+    print_config_schema: bool,
+    /// path to PEM certificate chain to serve TLS with
+    #[argh(option)]
+    tls_cert: Option<String>,
+    /// path to PEM private key of the certificate
+    #[argh(option)]
+    tls_key: Option<String>,
+    #[argh(subcommand)]
+    subcommand: Option<Subcommand>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Subcommand {
+    Check(CheckOptions),
+    Probe(ProbeOptions),
+}
+
+#[derive(FromArgs)]
+/// Check that the commands of the servers are found, and the SQL databases accept the admin credentials.
+#[argh(subcommand, name = "check")]
+struct CheckOptions {}
+
+#[derive(FromArgs)]
+/// Start a server, do the initialize and shutdown handshake, and show the capabilities.
+#[argh(subcommand, name = "probe")]
+struct ProbeOptions {
+    /// name of the server
+    #[argh(positional)]
+    name: String,
+    /// value for `${query.<name>}` placeholders as <name>=<value>, can be repeated
+    #[argh(option, from_str_fn(check::parse_query_param))]
+    query: Vec<(String, String)>,
+    /// seconds to wait for each response (default: 30)
+    #[argh(option, default = "30")]
+    timeout_secs: u64,
 }
 
 #[tokio::main]
@@ -67,39 +119,187 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (opts, commands, config) = get_opts_and_commands();
 
     let cwd = std::env::current_dir()?;
-    // TODO Move these to `api` module.
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_headers(&[http::header::CONTENT_TYPE])
-        .allow_methods(&[http::Method::GET, http::Method::OPTIONS, http::Method::POST]);
-    // TODO Limit concurrent connection. Can get messy when `sync` is used.
-    // TODO? Keep track of added files and remove them on disconnect?
+    let cwd_uri = Url::from_directory_path(&cwd).expect("valid url from current dir");
+    match &opts.subcommand {
+        Some(Subcommand::Check(_)) => {
+            let problems = check::check(config.as_ref(), commands.as_deref()).await;
+            if problems > 0 {
+                return Err(format!("{} problems found", problems).into());
+            }
+            return Ok(());
+        }
+        Some(Subcommand::Probe(probe)) => {
+            let query = probe.query.iter().cloned().collect::<HashMap<_, _>>();
+            return check::probe(
+                &probe.name,
+                config.as_ref(),
+                commands.as_deref(),
+                &cwd_uri,
+                &query,
+                Duration::from_secs(probe.timeout_secs),
+            )
+            .await
+            .map_err(|e| format!("Failed to probe {}: {}", probe.name, e).into());
+        }
+        None => {}
+    }
+    let pool = config
+        .as_ref()
+        .map(|config| Arc::new(api::pool::Pool::new(config, &cwd_uri)));
+    let workspaces = config
+        .as_ref()
+        .and_then(|config| config.workspace.clone())
+        .map(|workspace| Arc::new(api::workspace::Workspaces::new(workspace)));
+    let admission = Arc::new(api::admission::Admission::new(config.as_ref()));
+    let auth = config
+        .as_ref()
+        .and_then(|config| config.auth.as_ref())
+        .map(|auth| api::auth::Auth::new(auth).map(Arc::new))
+        .transpose()
+        .map_err(|e| format!("Failed to configure auth: {}", e))?;
+    let tls = match (opts.tls_cert.clone(), opts.tls_key.clone()) {
+        (Some(cert_path), Some(key_path)) => {
+            let tls = config.as_ref().and_then(|c| c.tls.clone());
+            Some(config::TlsConfig {
+                cert_path,
+                key_path,
+                client_ca_path: tls.as_ref().and_then(|t| t.client_ca_path.clone()),
+                reload_interval_secs: tls.map_or_else(config::default_reload_interval_secs, |t| {
+                    t.reload_interval_secs
+                }),
+            })
+        }
+        (None, None) => config.as_ref().and_then(|c| c.tls.clone()),
+        _ => return Err("--tls-cert and --tls-key must be used together".into()),
+    };
+    let tls = tls
+        .map(api::tls::Tls::new)
+        .transpose()
+        .map_err(|e| format!("Failed to configure TLS: {}", e))?;
+    let files_rate_limit = config
+        .as_ref()
+        .and_then(|c| c.rate_limit.as_ref())
+        .and_then(|r| r.files.clone())
+        .map(|limit| Arc::new(api::rate_limit::IpLimiter::new(limit)));
+    let shutdown = Arc::new(api::shutdown::Shutdown::default());
+    let shutdown_timeout = Duration::from_secs(
+        config
+            .as_ref()
+            .and_then(|c| c.shutdown_timeout_secs)
+            .unwrap_or(30),
+    );
+    let cors = api::cors::Cors::new(config.as_ref().and_then(|config| config.cors.as_ref()))
+        .map(Arc::new)
+        .map_err(|e| format!("Failed to configure CORS: {}", e))?;
+    let config = config.map(Arc::new);
+    // New connections use the config reloaded from the file on change or SIGHUP.
+    let config_updates = opts
+        .config
+        .clone()
+        .zip(config.clone())
+        .map(|(path, config)| api::reload::watch(path, config));
+    let sessions = Arc::new(api::session::Sessions::default());
     let proxy = api::proxy::handler(api::proxy::Context {
         commands,
         sync: opts.sync,
         remap: opts.remap,
-        cwd: Url::from_directory_path(&cwd).expect("valid url from current dir"),
-        config: config,
+        cwd: cwd_uri,
+        config,
+        config_updates,
+        pool,
+        sessions: sessions.clone(),
+        workspaces: workspaces.clone(),
+        admission,
+        shutdown: shutdown.clone(),
+        auth: auth.clone(),
+        cors: cors.clone(),
     });
-    let healthz = warp::path::end().and(warp::get()).map(|| "OK");
-    let addr = opts.listen.parse::<SocketAddr>().expect("valid addr");
+    // Skip WebSocket upgrades so that the ones rejected by `proxy` aren't answered with OK.
+    let healthz = warp::path::end()
+        .and(warp::get())
+        .and(warp::header::optional::<String>("upgrade"))
+        .and_then(|upgrade: Option<String>| async move {
+            match upgrade {
+                Some(_) => Err(warp::reject::not_found()),
+                None => Ok("OK"),
+            }
+        });
+    let listens = if opts.listen.is_empty() {
+        vec![Listen::Tcp(([0, 0, 0, 0], 9999).into())]
+    } else {
+        opts.listen.clone()
+    };
+    // Stop accepting connections and tell sessions to shut down on signal.
+    {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            tracing::info!("shutting down");
+            shutdown.request();
+        });
+    }
     // Enable `/files` endpoint if sync
     if opts.sync {
+        #[cfg(unix)]
+        if files_rate_limit.is_some()
+            && listens.iter().any(|l| matches!(l, listen::Listen::Unix(_)))
+        {
+            tracing::warn!("rate_limit.files is not applied to requests from unix sockets");
+        }
         let files = api::files::handler(api::files::Context {
             cwd,
             remap: opts.remap,
+            workspaces,
+            auth,
+            cors: cors.clone(),
+            rate_limit: files_rate_limit,
+            sessions,
         });
-        warp::serve(proxy.or(healthz).or(files).recover(api::recover).with(cors))
-            .run(addr)
-            .await;
+        listen::serve(
+            api::cors::wrap(proxy.or(healthz).or(files).recover(api::recover), cors),
+            &listens,
+            opts.socket_mode,
+            tls,
+            &shutdown,
+        )
+        .await?;
     } else {
-        warp::serve(proxy.or(healthz).recover(api::recover).with(cors))
-            .run(addr)
-            .await;
+        listen::serve(
+            api::cors::wrap(proxy.or(healthz).recover(api::recover), cors),
+            &listens,
+            opts.socket_mode,
+            tls,
+            &shutdown,
+        )
+        .await?;
+    }
+    // Any servers still running are killed when the runtime is dropped.
+    if tokio::time::timeout(shutdown_timeout, shutdown.wait())
+        .await
+        .is_err()
+    {
+        tracing::warn!("timed out waiting for sessions to shut down");
     }
     Ok(())
 }
 
+/// Resolves on SIGTERM or Ctrl-C.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("signal handler");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 fn get_opts_and_commands() -> (Options, Option<Vec<Vec<String>>>, Option<Config>) {
     let args: Vec<String> = std::env::args().collect();
     let splitted: Vec<Vec<String>> = args.split(|s| *s == "--").map(|s| s.to_vec()).collect();
@@ -120,6 +320,12 @@ fn get_opts_and_commands() -> (Options, Option<Vec<Vec<String>>>, Option<Config>
         std::process::exit(0);
     }
 
+    if opts.print_config_schema {
+        let schema = schemars::schema_for!(Config);
+        println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        std::process::exit(0);
+    }
+
     let commands = if splitted.len() < 2 {
         None
     } else {
@@ -127,8 +333,10 @@ fn get_opts_and_commands() -> (Options, Option<Vec<Vec<String>>>, Option<Config>
     };
 
     let config = if let Some(config) = &opts.config {
-        Some(read_config_from_file(config).unwrap_or_else(|e| {
-            panic!("Failed to read config file '{}': {}", config, e);
+        Some(config::read_config_from_file(config).unwrap_or_else(|e| {
+            // Also reached by `check`, so report the problem instead of panicking.
+            eprintln!("Failed to read config file '{}': {}", config, e);
+            std::process::exit(1);
         }))
     } else {
         None
@@ -136,27 +344,3 @@ fn get_opts_and_commands() -> (Options, Option<Vec<Vec<String>>>, Option<Config>
 
     (opts, commands, config)
 }
-
-fn read_config_from_file(file_path: &str) -> Result<Config, String> {
-    let raw = std::fs::read_to_string(file_path).map_err(|e| e.to_string())?;
-    let rendered = shellexpand::env_with_context(&raw, |s| match std::env::var(s) {
-        Ok(value) => Ok(Some(value)),
-        Err(std::env::VarError::NotPresent) => Ok(Some(String::new())),
-        Err(e) => Err(e),
-    })
-    .map_err(|e| e.to_string())?;
-    let u = serde_json::from_str(&rendered).map_err(|e| e.to_string())?;
-    Ok(u)
-}
-
-fn parse_listen(value: &str) -> Result<String, String> {
-    // Allow specifying only a port number.
-    if value.chars().all(|c| c.is_ascii_digit()) {
-        return Ok(format!("0.0.0.0:{}", value));
-    }
-
-    match value.parse::<SocketAddr>() {
-        Ok(_) => Ok(String::from(value)),
-        Err(_) => Err(format!("{} cannot be parsed as SocketAddr", value)),
-    }
-}